@@ -0,0 +1,14 @@
+-- Tokens read by `SqlTokenStore` (features `sqlite` and `postgres`)
+CREATE TABLE IF NOT EXISTS api_tokens (
+    -- Hex encoded SHA-256 of the token, see `SqlTokenStore::token_hash`
+    token_hash TEXT PRIMARY KEY,
+    -- Identity of the token; tokens without a name have no identity
+    name TEXT,
+    client_id TEXT,
+    -- Space separated scopes
+    scopes TEXT NOT NULL DEFAULT '',
+    -- Validity window in seconds since the Unix epoch
+    not_before BIGINT,
+    expires_at BIGINT,
+    revoked BOOLEAN NOT NULL DEFAULT FALSE
+)
@@ -0,0 +1,439 @@
+//! Caching of validation results from slow backends
+
+use crate::hash::Digest;
+use crate::{ApiTokenError, Clock, Credentials, Grant, SystemClock, TokenValidator};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+type Outcome = Result<Arc<Grant>, ApiTokenError>;
+
+/// The longest time a result is kept, whatever the TTL
+const MAX_TTL: Duration = Duration::from_secs(100 * 365 * 24 * 60 * 60);
+
+struct Cached {
+    outcome: Outcome,
+    expires: SystemTime,
+}
+
+/// Cached outcomes, indexed by key and by expiry so that evicting is cheap
+#[derive(Default)]
+struct Entries {
+    by_key: HashMap<Digest, Cached>,
+    by_expiry: BTreeSet<(SystemTime, Digest)>,
+}
+
+impl Entries {
+    fn len(&self) -> usize {
+        self.by_key.len()
+    }
+
+    fn insert(&mut self, key: Digest, cached: Cached) {
+        self.by_expiry.insert((cached.expires, key));
+        if let Some(previous) = self.by_key.insert(key, cached) {
+            self.by_expiry.remove(&(previous.expires, key));
+        }
+    }
+
+    fn remove(&mut self, key: &Digest) {
+        if let Some(cached) = self.by_key.remove(key) {
+            self.by_expiry.remove(&(cached.expires, *key));
+        }
+    }
+
+    /// Remove the entry closest to expiry, or furthest past it
+    fn pop_soonest(&mut self) -> bool {
+        match self.by_expiry.pop_first() {
+            Some((_, key)) => self.by_key.remove(&key).is_some(),
+            None => false,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.by_key.clear();
+        self.by_expiry.clear();
+    }
+}
+
+/// A [`TokenValidator`] that remembers the results of another one for a while
+///
+/// Accepted credentials are remembered for the positive TTL, and credentials the
+/// backend reported as unknown or revoked for the (shorter) negative TTL. Other
+/// failures, such as [`ApiTokenError::Unavailable`], are never cached. Once the cache
+/// holds `capacity` entries, expired entries are dropped first and then the ones
+/// closest to expiry.
+///
+/// Credentials are keyed by an HMAC under a key generated for each cache, so neither
+/// tokens nor plain hashes of them are kept. Within a request, the result is shared by
+/// all guards through Rocket's request-local cache either way; this cache spans
+/// requests.
+///
+/// [`ApiToken::revoke`](crate::ApiToken::revoke) invalidates the revoked token. When
+/// tokens are revoked in the backend directly, keep an `Arc` of the cache (it is itself
+/// a validator) and call [`invalidate`](TokenValidator::invalidate) or
+/// [`invalidate_all`](TokenValidator::invalidate_all).
+///
+/// ```
+/// # use rocket_apitoken::{ApiToken, ApiTokenError, CachedValidator, Credentials, Grant, TokenValidator};
+/// # use std::sync::Arc;
+/// # use std::time::Duration;
+/// # struct Introspection;
+/// # #[rocket::async_trait]
+/// # impl TokenValidator for Introspection {
+/// #     async fn validate(&self, _: &Credentials<'_>) -> Result<Arc<Grant>, ApiTokenError> {
+/// #         Err(ApiTokenError::UnknownToken)
+/// #     }
+/// # }
+/// let cached = Arc::new(
+///     CachedValidator::new(Introspection)
+///         .with_ttl(Duration::from_secs(300))
+///         .with_capacity(50_000),
+/// );
+/// let api_token = ApiToken::new(vec![], true).with_validator(cached.clone());
+///
+/// println!("hit rate: {:.2}", cached.stats().hit_rate());
+/// ```
+pub struct CachedValidator<V> {
+    inner: V,
+    key: [u8; 32],
+    entries: Mutex<Entries>,
+    capacity: usize,
+    ttl: Duration,
+    negative_ttl: Duration,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    clock: Arc<dyn Clock>,
+}
+
+/// Counters of a [`CachedValidator`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Lookups answered from the cache
+    pub hits: u64,
+    /// Lookups passed on to the backend
+    pub misses: u64,
+    /// Entries dropped to stay within capacity
+    pub evictions: u64,
+    /// Entries currently held
+    pub entries: usize,
+}
+
+impl CacheStats {
+    /// The fraction of lookups answered from the cache
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            total => self.hits as f64 / total as f64,
+        }
+    }
+}
+
+impl<V: TokenValidator> CachedValidator<V> {
+    /// Cache the results of `inner`
+    ///
+    /// By default up to 10000 results are kept, accepted credentials for 60 seconds
+    /// and rejected ones for 10 seconds.
+    pub fn new(inner: V) -> Self {
+        Self {
+            inner,
+            key: rand::random(),
+            entries: Mutex::default(),
+            capacity: 10_000,
+            ttl: Duration::from_secs(60),
+            negative_ttl: Duration::from_secs(10),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Set the maximum number of cached results
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Set how long accepted credentials are remembered
+    ///
+    /// TTLs beyond 100 years, such as `Duration::MAX`, are cut to 100 years.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Set how long unknown and revoked credentials are remembered
+    pub fn with_negative_ttl(mut self, ttl: Duration) -> Self {
+        self.negative_ttl = ttl;
+        self
+    }
+
+    /// Set the clock that cached results expire by
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// The hit, miss and eviction counters
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            entries: self.entries().len(),
+        }
+    }
+
+    fn entries(&self) -> std::sync::MutexGuard<'_, Entries> {
+        self.entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn digest(&self, credentials: &Credentials<'_>) -> Digest {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts any key size");
+        match credentials.username() {
+            Some(username) => {
+                mac.update(b"basic\0");
+                mac.update(username.as_bytes());
+                mac.update(b"\0");
+            }
+            None => mac.update(b"token\0"),
+        }
+        mac.update(credentials.secret().as_bytes());
+        mac.finalize().into_bytes().into()
+    }
+
+    fn lookup(&self, key: &Digest) -> Option<Outcome> {
+        let mut entries = self.entries();
+        match entries.by_key.get(key) {
+            Some(cached) if cached.expires > self.clock.now() => Some(cached.outcome.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn store(&self, key: Digest, outcome: &Outcome) {
+        let ttl = match outcome {
+            Ok(_) => self.ttl,
+            Err(ApiTokenError::UnknownToken | ApiTokenError::Revoked) => self.negative_ttl,
+            Err(_) => return,
+        };
+        if self.capacity == 0 || ttl.is_zero() {
+            return;
+        }
+        let Some(expires) = self.clock.now().checked_add(ttl.min(MAX_TTL)) else {
+            return;
+        };
+        let mut entries = self.entries();
+        if !entries.by_key.contains_key(&key) {
+            while entries.len() >= self.capacity && entries.pop_soonest() {
+                self.evictions.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        entries.insert(
+            key,
+            Cached {
+                outcome: outcome.clone(),
+                expires,
+            },
+        );
+    }
+}
+
+#[rocket::async_trait]
+impl<V: TokenValidator> TokenValidator for CachedValidator<V> {
+    async fn validate(&self, credentials: &Credentials<'_>) -> Result<Arc<Grant>, ApiTokenError> {
+        let key = self.digest(credentials);
+        if let Some(outcome) = self.lookup(&key) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return outcome;
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let outcome = self.inner.validate(credentials).await;
+        self.store(key, &outcome);
+        outcome
+    }
+
+    fn invalidate(&self, credentials: &Credentials<'_>) {
+        self.entries().remove(&self.digest(credentials));
+        self.inner.invalidate(credentials);
+    }
+
+    fn invalidate_all(&self) {
+        self.entries().clear();
+        self.inner.invalidate_all();
+    }
+}
+
+impl<V> fmt::Debug for CachedValidator<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CachedValidator")
+            .field("capacity", &self.capacity)
+            .field("ttl", &self.ttl)
+            .field("negative_ttl", &self.negative_ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ApiToken, Identity};
+    use std::sync::atomic::AtomicUsize;
+    use std::time::UNIX_EPOCH;
+
+    /// Accepts `good*` tokens, is down for `down`, and counts its calls
+    #[derive(Default)]
+    struct Backend {
+        calls: AtomicUsize,
+    }
+
+    #[rocket::async_trait]
+    impl TokenValidator for Backend {
+        async fn validate(
+            &self,
+            credentials: &Credentials<'_>,
+        ) -> Result<Arc<Grant>, ApiTokenError> {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            match credentials.secret() {
+                "down" => Err(ApiTokenError::Unavailable),
+                secret if secret.starts_with("good") => {
+                    Ok(Arc::new(Grant::new().identity(Identity::new(secret))))
+                }
+                _ => Err(ApiTokenError::UnknownToken),
+            }
+        }
+    }
+
+    fn token(token: &str) -> Credentials<'_> {
+        Credentials::Token(token.into())
+    }
+
+    fn calls(cache: &CachedValidator<Arc<Backend>>) -> usize {
+        cache.inner.calls.load(Ordering::Relaxed)
+    }
+
+    /// The time read by the clock of the caches built by [`cache`]
+    type Now = Arc<Mutex<SystemTime>>;
+
+    fn cache() -> (CachedValidator<Arc<Backend>>, Now) {
+        let now = Arc::new(Mutex::new(UNIX_EPOCH + Duration::from_secs(1_000_000)));
+        let clock = now.clone();
+        let cache = CachedValidator::new(Arc::new(Backend::default()))
+            .with_clock(move || *clock.lock().unwrap());
+        (cache, now)
+    }
+
+    fn advance(now: &Now, by: Duration) {
+        *now.lock().unwrap() += by;
+    }
+
+    #[rocket::async_test]
+    async fn caches_accepted_tokens_for_ttl() {
+        let (cache, now) = cache();
+        let cache = cache.with_ttl(Duration::from_secs(60));
+        assert!(cache.validate(&token("good")).await.is_ok());
+        advance(&now, Duration::from_secs(59));
+        assert!(cache.validate(&token("good")).await.is_ok());
+        assert_eq!(calls(&cache), 1);
+        advance(&now, Duration::from_secs(1));
+        assert!(cache.validate(&token("good")).await.is_ok());
+        assert_eq!(calls(&cache), 2);
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (1, 2));
+    }
+
+    #[rocket::async_test]
+    async fn caches_rejected_tokens_for_negative_ttl() {
+        let (cache, now) = cache();
+        let cache = cache.with_negative_ttl(Duration::from_secs(10));
+        for _ in 0..2 {
+            let result = cache.validate(&token("bad")).await;
+            assert!(matches!(result, Err(ApiTokenError::UnknownToken)));
+        }
+        assert_eq!(calls(&cache), 1);
+        advance(&now, Duration::from_secs(10));
+        let _ = cache.validate(&token("bad")).await;
+        assert_eq!(calls(&cache), 2);
+    }
+
+    #[rocket::async_test]
+    async fn does_not_cache_backend_failures() {
+        let (cache, _) = cache();
+        for _ in 0..2 {
+            let result = cache.validate(&token("down")).await;
+            assert!(matches!(result, Err(ApiTokenError::Unavailable)));
+        }
+        assert_eq!(calls(&cache), 2);
+        assert_eq!(cache.stats().entries, 0);
+    }
+
+    #[rocket::async_test]
+    async fn evicts_entries_closest_to_expiry_at_capacity() {
+        let (cache, _) = cache();
+        let cache = cache
+            .with_capacity(2)
+            .with_ttl(Duration::from_secs(60))
+            .with_negative_ttl(Duration::from_secs(10));
+        let _ = cache.validate(&token("good-1")).await;
+        let _ = cache.validate(&token("bad")).await;
+        let _ = cache.validate(&token("good-2")).await;
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.evictions), (2, 1));
+        let _ = cache.validate(&token("good-1")).await;
+        let _ = cache.validate(&token("good-2")).await;
+        assert_eq!(calls(&cache), 3);
+        let _ = cache.validate(&token("bad")).await;
+        assert_eq!(calls(&cache), 4);
+    }
+
+    #[rocket::async_test]
+    async fn refreshed_entries_keep_a_single_expiry() {
+        let (cache, now) = cache();
+        let cache = cache.with_capacity(2).with_ttl(Duration::from_secs(60));
+        let _ = cache.validate(&token("good-1")).await;
+        advance(&now, Duration::from_secs(60));
+        let _ = cache.validate(&token("good-1")).await;
+        let _ = cache.validate(&token("good-2")).await;
+        let _ = cache.validate(&token("good-3")).await;
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.evictions), (2, 1));
+        assert_eq!(cache.entries().by_expiry.len(), 2);
+    }
+
+    #[rocket::async_test]
+    async fn revoke_invalidates_cached_token() {
+        let cache = Arc::new(cache().0);
+        let api_token = ApiToken::new(vec![], true).with_validator(cache.clone());
+        let credentials = token("good");
+        assert!(api_token.check(&credentials).await.is_ok());
+        assert_eq!(cache.stats().entries, 1);
+        api_token.revoke("good");
+        assert_eq!(cache.stats().entries, 0);
+        assert!(api_token.check(&credentials).await.is_ok());
+        assert_eq!(calls(&cache), 2);
+    }
+
+    #[rocket::async_test]
+    async fn caches_with_unbounded_ttl() {
+        let (cache, now) = cache();
+        let cache = cache
+            .with_ttl(Duration::MAX)
+            .with_negative_ttl(Duration::MAX);
+        assert!(cache.validate(&token("good")).await.is_ok());
+        let _ = cache.validate(&token("bad")).await;
+        advance(&now, Duration::from_secs(50 * 365 * 24 * 60 * 60));
+        assert!(cache.validate(&token("good")).await.is_ok());
+        let _ = cache.validate(&token("bad")).await;
+        assert_eq!(calls(&cache), 2);
+    }
+}
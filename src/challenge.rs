@@ -0,0 +1,198 @@
+//! `WWW-Authenticate` challenges for failed authorization
+
+use crate::rate::{self, RateLimitState};
+use crate::{ApiToken, ApiTokenError, TokenSource};
+use rocket::http::{ContentType, Header, Status};
+use rocket::response::{self, Responder, Response};
+use rocket::{Catcher, Request};
+use std::io::Cursor;
+
+/// The realm of `Basic` challenges when none is configured, as RFC 7617 requires one
+const DEFAULT_REALM: &str = "api";
+
+/// The failure reported by a guard of this crate, for catchers to pick up
+pub(crate) struct Failure(pub(crate) Option<ApiTokenError>);
+
+impl ApiTokenError {
+    /// The RFC 6750 `WWW-Authenticate` challenge for this failure
+    ///
+    /// Returns `None` for failures that are not about the presented credentials. The
+    /// `Basic` challenge that [`Challenge`] adds when Basic users exist is not included.
+    pub fn challenge(&self, realm: Option<&str>) -> Option<String> {
+        let mut params = Vec::new();
+        if let Some(realm) = realm {
+            params.push(format!("realm=\"{}\"", quote(realm)));
+        }
+        match self {
+            ApiTokenError::MissingHeader => {}
+            ApiTokenError::MalformedScheme => {
+                params.push("error=\"invalid_request\"".to_string());
+            }
+            ApiTokenError::UnknownToken
+            | ApiTokenError::Expired
+            | ApiTokenError::NotYetValid
+            | ApiTokenError::Revoked => {
+                params.push("error=\"invalid_token\"".to_string());
+                params.push(format!(
+                    "error_description=\"{}\"",
+                    quote(&self.to_string())
+                ));
+            }
+            ApiTokenError::InsufficientScope { scope } => {
+                params.push("error=\"insufficient_scope\"".to_string());
+                params.push(format!("scope=\"{}\"", quote(scope)));
+            }
+            ApiTokenError::RateLimited { .. }
+            | ApiTokenError::LockedOut { .. }
+            | ApiTokenError::MissingState
+            | ApiTokenError::Unavailable => return None,
+        }
+        Some(challenge(&params))
+    }
+}
+
+impl ApiToken {
+    /// The RFC 7617 `Basic` challenge, if Basic credentials are accepted
+    ///
+    /// They are when the `Authorization` header is a source and at least one
+    /// [`Token::basic`](crate::Token::basic) user is registered.
+    fn basic_challenge(&self) -> Option<String> {
+        if !self.sources.contains(&TokenSource::Authorization) || !self.store.has_basic() {
+            return None;
+        }
+        let realm = self.realm.as_deref().unwrap_or(DEFAULT_REALM);
+        Some(format!(
+            "Basic realm=\"{}\", charset=\"UTF-8\"",
+            quote(realm)
+        ))
+    }
+}
+
+fn challenge(params: &[String]) -> String {
+    match params.is_empty() {
+        true => "Bearer".to_string(),
+        false => format!("Bearer {}", params.join(", ")),
+    }
+}
+
+/// Escape a value for use in a quoted-string
+fn quote(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Error response carrying the `WWW-Authenticate` challenges for a failed request
+///
+/// Unauthorized requests also get a `Basic` challenge when the managed `ApiToken`
+/// accepts HTTP Basic credentials, so that browsers and tools prompt for them.
+///
+/// Built by the catchers returned from [`catchers`], which cover the status codes the
+/// guards of this crate fail with. Custom catchers can build it with
+/// [`Challenge::new`] to keep the header while changing the body.
+#[derive(Debug, Clone)]
+pub struct Challenge {
+    status: Status,
+    error: Option<ApiTokenError>,
+    challenges: Vec<String>,
+}
+
+impl Challenge {
+    /// Build the response for a request that failed with `status`
+    ///
+    /// The challenge reflects the failure reported by this crate's guards, if any, and
+    /// the realm configured on the managed `ApiToken`.
+    pub fn new(status: Status, request: &Request<'_>) -> Self {
+        let error = request.local_cache(|| Failure(None)).0.clone();
+        let api_token = request.rocket().state::<ApiToken>();
+        let realm = api_token.and_then(|api_token| api_token.realm.as_deref());
+        let bearer = match &error {
+            Some(error) => error.challenge(realm),
+            // A 401 must carry a challenge even if another guard caused it.
+            None if status == Status::Unauthorized => Some(challenge(
+                &realm
+                    .map(|realm| vec![format!("realm=\"{}\"", quote(realm))])
+                    .unwrap_or_default(),
+            )),
+            None => None,
+        };
+        let basic = match status == Status::Unauthorized && bearer.is_some() {
+            true => api_token.and_then(ApiToken::basic_challenge),
+            false => None,
+        };
+        Self {
+            status,
+            error,
+            challenges: bearer.into_iter().chain(basic).collect(),
+        }
+    }
+
+    /// The failure reported by this crate's guards, if any
+    pub fn error(&self) -> Option<&ApiTokenError> {
+        self.error.as_ref()
+    }
+}
+
+impl<'r> Responder<'r, 'static> for Challenge {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let body = match &self.error {
+            Some(error) => error.to_string(),
+            None => self.status.reason_lossy().to_string(),
+        };
+        let mut response = Response::build();
+        response
+            .status(self.status)
+            .header(ContentType::Plain)
+            .sized_body(body.len(), Cursor::new(body));
+        for challenge in self.challenges {
+            response.header_adjoin(Header::new("WWW-Authenticate", challenge));
+        }
+        if let Some(
+            ApiTokenError::RateLimited { retry_after } | ApiTokenError::LockedOut { retry_after },
+        ) = self.error
+        {
+            let retry_after = rate::ceil_secs(retry_after).max(1);
+            response.header(Header::new("Retry-After", retry_after.to_string()));
+        }
+        if let Some(state) = RateLimitState::of(request) {
+            for header in state.headers() {
+                response.header(header);
+            }
+        }
+        response.ok()
+    }
+}
+
+#[rocket::catch(401)]
+fn unauthorized(request: &Request<'_>) -> Challenge {
+    Challenge::new(Status::Unauthorized, request)
+}
+
+#[rocket::catch(403)]
+fn forbidden(request: &Request<'_>) -> Challenge {
+    Challenge::new(Status::Forbidden, request)
+}
+
+#[rocket::catch(429)]
+fn too_many_requests(request: &Request<'_>) -> Challenge {
+    Challenge::new(Status::TooManyRequests, request)
+}
+
+/// Catchers that add RFC 6750 `WWW-Authenticate` challenges to failed requests
+///
+/// Unauthorized requests also get an RFC 7617 `Basic` challenge when Basic users exist.
+///
+/// Requests over their [`RateLimit`](crate::RateLimit) get `Retry-After` and
+/// `RateLimit-*` headers instead, and locked out clients `Retry-After`.
+///
+/// ```no_run
+/// # #[macro_use] extern crate rocket;
+/// # use rocket_apitoken::ApiTokenFairing;
+/// #[launch]
+/// fn rocket() -> _ {
+///     rocket::build()
+///         .attach(ApiTokenFairing)
+///         .register("/", rocket_apitoken::catchers())
+/// }
+/// ```
+pub fn catchers() -> Vec<Catcher> {
+    rocket::catchers![unauthorized, forbidden, too_many_requests]
+}
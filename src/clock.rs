@@ -0,0 +1,29 @@
+//! Time source for token expiry checks
+
+use std::time::SystemTime;
+
+/// Source of the current time used to check token validity windows
+///
+/// The system clock is used by default; install a different one with
+/// [`ApiToken::with_clock`](crate::ApiToken::with_clock), e.g. to make tests
+/// deterministic. Any `Fn() -> SystemTime` closure is a clock.
+pub trait Clock: Send + Sync {
+    /// The current time
+    fn now(&self) -> SystemTime;
+}
+
+/// The system clock
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+impl<F: Fn() -> SystemTime + Send + Sync> Clock for F {
+    fn now(&self) -> SystemTime {
+        self()
+    }
+}
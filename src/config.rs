@@ -0,0 +1,516 @@
+//! Loading `ApiToken` from Rocket's configuration
+
+use crate::{
+    ApiToken, Identity, InvalidTokenHash, Lockout, RateLimit, Token, TokenHash, TokenSource,
+};
+#[cfg(feature = "jwt")]
+use crate::{JwtKey, JwtValidator};
+use rocket::figment::{self, Figment};
+use rocket::serde::Deserialize;
+use rocket::time::format_description::well_known::Rfc3339;
+use rocket::time::OffsetDateTime;
+use std::collections::BTreeMap;
+use std::fmt;
+#[cfg(feature = "jwt")]
+use std::path::PathBuf;
+#[cfg(feature = "jwt")]
+use std::time::Duration;
+use std::time::SystemTime;
+
+/// The configuration section `ApiToken` is read from
+pub const CONFIG_KEY: &str = "apitoken";
+
+/// Token configuration as read from `Rocket.toml` or `ROCKET_APITOKEN`
+///
+/// ```toml
+/// [default.apitoken]
+/// enabled = true
+/// sources = ["authorization", { header = "X-API-Key" }]
+/// rate_limit = { requests = 100, period = 60 }
+/// lockout = { threshold = 5 }
+/// tokens = [
+///     "plaintext-token",
+///     { hash = "sha256:9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08", name = "dashboard", scopes = ["read"] },
+///     { username = "prometheus", token = "scrape-password", scopes = ["metrics"] },
+/// ]
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ApiTokenConfig {
+    /// Whether authorization is enforced; defaults to `true`
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// The valid tokens
+    #[serde(default)]
+    pub tokens: Vec<TokenConfig>,
+    /// The realm announced in `WWW-Authenticate` challenges
+    #[serde(default)]
+    pub realm: Option<String>,
+    /// Where the token is read from, in order of preference
+    pub sources: Option<Vec<TokenSource>>,
+    /// The default request budget of each token or identity
+    pub rate_limit: Option<RateLimit>,
+    /// Blocking of clients after repeated failed attempts
+    pub lockout: Option<Lockout>,
+    /// Accept JWT bearer tokens as well
+    #[cfg(feature = "jwt")]
+    pub jwt: Option<JwtConfig>,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+/// A single configured token
+///
+/// Either a plaintext token string or a table describing the token.
+#[derive(Clone, Deserialize)]
+#[serde(crate = "rocket::serde", untagged)]
+#[allow(clippy::large_enum_variant)] // only built while loading configuration
+pub enum TokenConfig {
+    /// A plaintext token without identity or scopes
+    Plain(String),
+    /// A token with identity and scopes
+    Detailed(TokenTable),
+}
+
+/// A configured token described by a table
+///
+/// Exactly one of `token` and `hash` must be set. With `username`, the secret is the
+/// password of HTTP Basic credentials.
+#[derive(Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct TokenTable {
+    /// The plaintext token
+    pub token: Option<String>,
+    /// The token hash, in any format accepted by `TokenHash::from_str`
+    pub hash: Option<String>,
+    /// The HTTP Basic username; the identity name defaults to it
+    pub username: Option<String>,
+    /// The identity name
+    pub name: Option<String>,
+    /// The identity client id
+    pub client_id: Option<String>,
+    /// The identity metadata
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+    /// The scopes granted to the token
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    /// RFC 3339 time before which the token is rejected
+    pub not_before: Option<String>,
+    /// RFC 3339 time from which the token is rejected
+    pub expires_at: Option<String>,
+    /// The request budget of the token, instead of the default
+    pub rate_limit: Option<RateLimit>,
+}
+
+/// JWT validation settings, read from the `jwt` table of the `apitoken` section
+///
+/// ```toml
+/// [default.apitoken.jwt]
+/// jwks_file = "/etc/myapp/jwks.json"
+/// issuer = ["https://auth.example.com"]
+/// audience = ["api"]
+/// ```
+#[cfg(feature = "jwt")]
+#[derive(Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct JwtConfig {
+    /// Shared secret for `HS256` tokens
+    pub secret: Option<String>,
+    /// PEM file holding an RSA (`RS256`) or Ed25519 (`EdDSA`) public key
+    pub public_key_file: Option<PathBuf>,
+    /// JWK set file
+    pub jwks_file: Option<PathBuf>,
+    /// The accepted issuers
+    #[serde(default)]
+    pub issuer: Vec<String>,
+    /// The accepted audiences
+    #[serde(default)]
+    pub audience: Vec<String>,
+    /// Allowed clock skew in seconds
+    pub leeway: Option<u64>,
+}
+
+#[cfg(feature = "jwt")]
+impl fmt::Debug for JwtConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JwtConfig")
+            .field("secret", &self.secret.as_ref().map(|_| "<redacted>"))
+            .field("public_key_file", &self.public_key_file)
+            .field("jwks_file", &self.jwks_file)
+            .field("issuer", &self.issuer)
+            .field("audience", &self.audience)
+            .field("leeway", &self.leeway)
+            .finish()
+    }
+}
+
+#[cfg(feature = "jwt")]
+impl JwtConfig {
+    pub(crate) fn into_validator(self) -> Result<JwtValidator, ConfigError> {
+        let invalid = |reason: String| ConfigError::InvalidJwtKey { reason };
+        let mut keys = Vec::new();
+        if let Some(secret) = self.secret {
+            keys.push(JwtKey::hs256(secret.as_bytes()));
+        }
+        if let Some(path) = self.public_key_file {
+            let pem = std::fs::read(&path)
+                .map_err(|error| invalid(format!("{}: {}", path.display(), error)))?;
+            let key = JwtKey::rs256_pem(&pem)
+                .or_else(|_| JwtKey::eddsa_pem(&pem))
+                .map_err(|error| invalid(format!("{}: {}", path.display(), error)))?;
+            keys.push(key);
+        }
+        let mut validator = match self.jwks_file {
+            Some(path) => JwtValidator::from_jwks_file(&path)
+                .map_err(|error| invalid(format!("{}: {}", path.display(), error)))?
+                .with_keys(keys),
+            None if keys.is_empty() => return Err(invalid("no keys configured".to_string())),
+            None => JwtValidator::new(keys),
+        };
+        for issuer in self.issuer {
+            validator = validator.with_issuer(issuer);
+        }
+        for audience in self.audience {
+            validator = validator.with_audience(audience);
+        }
+        if let Some(leeway) = self.leeway {
+            validator = validator.with_leeway(Duration::from_secs(leeway));
+        }
+        Ok(validator)
+    }
+}
+
+impl fmt::Debug for TokenConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenConfig::Plain(_) => f.write_str("Plain(<redacted>)"),
+            TokenConfig::Detailed(table) => f.debug_tuple("Detailed").field(table).finish(),
+        }
+    }
+}
+
+impl fmt::Debug for TokenTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenTable")
+            .field("token", &self.token.as_ref().map(|_| "<redacted>"))
+            .field("hash", &self.hash)
+            .field("username", &self.username)
+            .field("name", &self.name)
+            .field("client_id", &self.client_id)
+            .field("metadata", &self.metadata)
+            .field("scopes", &self.scopes)
+            .field("not_before", &self.not_before)
+            .field("expires_at", &self.expires_at)
+            .field("rate_limit", &self.rate_limit)
+            .finish()
+    }
+}
+
+impl TokenConfig {
+    pub(crate) fn into_token(self, index: usize) -> Result<Token, ConfigError> {
+        let table = match self {
+            TokenConfig::Plain(token) => return Ok(Token::bearer(token)),
+            TokenConfig::Detailed(table) => table,
+        };
+        let parse_hash = |hash: String| {
+            hash.parse::<TokenHash>()
+                .map_err(|source| ConfigError::InvalidHash { index, source })
+        };
+        let token = match (table.token, table.hash, table.username) {
+            (Some(token), None, None) => Token::bearer(token),
+            (None, Some(hash), None) => Token::hashed(parse_hash(hash)?),
+            (Some(password), None, Some(username)) => Token::basic(username, password),
+            (None, Some(hash), Some(username)) => Token::basic_hashed(username, parse_hash(hash)?),
+            (Some(_), Some(_), _) => return Err(ConfigError::AmbiguousSecret { index }),
+            (None, None, _) => return Err(ConfigError::MissingSecret { index }),
+        };
+        let token = match table.name {
+            Some(name) => {
+                let mut identity = Identity::new(name);
+                if let Some(client_id) = table.client_id {
+                    identity = identity.with_client_id(client_id);
+                }
+                for (key, value) in table.metadata {
+                    identity = identity.with_metadata(key, value);
+                }
+                token.identity(identity)
+            }
+            None => token,
+        };
+        let mut token = token.scopes(table.scopes);
+        if let Some(time) = table.not_before {
+            token = token.not_before(parse_time(&time, index)?);
+        }
+        if let Some(time) = table.expires_at {
+            token = token.expires_at(parse_time(&time, index)?);
+        }
+        if let Some(limit) = table.rate_limit {
+            token = token.rate_limit(limit);
+        }
+        Ok(token)
+    }
+}
+
+fn parse_time(time: &str, index: usize) -> Result<SystemTime, ConfigError> {
+    OffsetDateTime::parse(time, &Rfc3339)
+        .map(SystemTime::from)
+        .map_err(|_| ConfigError::InvalidTime { index })
+}
+
+/// Error in the token configuration
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ConfigError {
+    /// The token at `index` has an unparseable hash
+    InvalidHash {
+        /// Position of the token in the configured list
+        index: usize,
+        /// The parse error
+        source: InvalidTokenHash,
+    },
+    /// The token at `index` sets both `token` and `hash`
+    AmbiguousSecret {
+        /// Position of the token in the configured list
+        index: usize,
+    },
+    /// The token at `index` sets neither `token` nor `hash`
+    MissingSecret {
+        /// Position of the token in the configured list
+        index: usize,
+    },
+    /// The token at `index` has a validity time that is not valid RFC 3339
+    InvalidTime {
+        /// Position of the token in the configured list
+        index: usize,
+    },
+    /// The token at `index` is empty
+    EmptyToken {
+        /// Position of the token in the configured list
+        index: usize,
+    },
+    /// The token at `index` repeats an earlier token
+    DuplicateToken {
+        /// Position of the token in the configured list
+        index: usize,
+    },
+    /// The token at `index` has an Argon2 or bcrypt hash but no username to look it up by
+    SlowHashWithoutUsername {
+        /// Position of the token in the configured list
+        index: usize,
+    },
+    /// Authorization is enabled but no tokens are configured
+    NoTokens,
+    /// The JWT keys could not be loaded
+    #[cfg(feature = "jwt")]
+    InvalidJwtKey {
+        /// What went wrong
+        reason: String,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::InvalidHash { index, source } => write!(f, "token {}: {}", index, source),
+            ConfigError::AmbiguousSecret { index } => {
+                write!(
+                    f,
+                    "token {}: only one of `token` and `hash` may be set",
+                    index
+                )
+            }
+            ConfigError::MissingSecret { index } => {
+                write!(f, "token {}: one of `token` and `hash` must be set", index)
+            }
+            ConfigError::InvalidTime { index } => {
+                write!(f, "token {}: times must be in RFC 3339 format", index)
+            }
+            ConfigError::EmptyToken { index } => write!(f, "token {}: token is empty", index),
+            ConfigError::DuplicateToken { index } => {
+                write!(f, "token {}: duplicates an earlier token", index)
+            }
+            ConfigError::SlowHashWithoutUsername { index } => {
+                write!(
+                    f,
+                    "token {}: argon2 and bcrypt hashes need a `username`",
+                    index
+                )
+            }
+            ConfigError::NoTokens => {
+                f.write_str("authorization is enabled but no tokens are configured")
+            }
+            #[cfg(feature = "jwt")]
+            ConfigError::InvalidJwtKey { reason } => write!(f, "invalid JWT keys: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::InvalidHash { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl TryFrom<ApiTokenConfig> for ApiToken {
+    type Error = ConfigError;
+
+    fn try_from(config: ApiTokenConfig) -> Result<Self, Self::Error> {
+        let tokens = config
+            .tokens
+            .into_iter()
+            .enumerate()
+            .map(|(index, token)| token.into_token(index))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut api_token = ApiToken::from_tokens(tokens, config.enabled);
+        if let Some(realm) = config.realm {
+            api_token = api_token.with_realm(realm);
+        }
+        if let Some(sources) = config.sources {
+            api_token = api_token.with_sources(sources);
+        }
+        if let Some(limit) = config.rate_limit {
+            api_token = api_token.with_rate_limit(limit);
+        }
+        if let Some(lockout) = config.lockout {
+            api_token = api_token.with_lockout(lockout);
+        }
+        #[cfg(feature = "jwt")]
+        if let Some(jwt) = config.jwt {
+            api_token = api_token.with_jwt(jwt.into_validator()?);
+        }
+        Ok(api_token)
+    }
+}
+
+impl ApiToken {
+    /// Create a new `ApiToken` instance from the `apitoken` section of a figment
+    ///
+    /// Pass `rocket.figment()` to read `[default.apitoken]` from `Rocket.toml` or
+    /// `ROCKET_APITOKEN` from the environment.
+    #[allow(clippy::result_large_err)] // matches `Figment::extract`
+    pub fn from_figment(figment: &Figment) -> Result<Self, figment::Error> {
+        let config: ApiTokenConfig = figment.focus(CONFIG_KEY).extract()?;
+        ApiToken::try_from(config)
+            .map_err(|error| figment::Error::from(error.to_string()).with_path(CONFIG_KEY))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use figment::providers::{Env, Format, Toml};
+    use figment::Jail;
+
+    fn config(toml: &str) -> ApiTokenConfig {
+        Figment::from(Toml::string(toml))
+            .focus(CONFIG_KEY)
+            .extract()
+            .unwrap()
+    }
+
+    fn token(toml: &str) -> Result<Token, ConfigError> {
+        config(&format!("[apitoken]\ntokens = [{toml}]"))
+            .tokens
+            .remove(0)
+            .into_token(0)
+    }
+
+    #[test]
+    fn parses_plain_and_detailed_tokens() {
+        let config = config(
+            r#"
+            [apitoken]
+            tokens = [
+                "plain-token",
+                { token = "detailed-token", name = "dashboard", scopes = ["read"] },
+                { username = "prometheus", token = "s3cret" },
+            ]
+            "#,
+        );
+        assert!(config.enabled);
+        assert!(matches!(&config.tokens[0], TokenConfig::Plain(token) if token == "plain-token"));
+        let TokenConfig::Detailed(table) = &config.tokens[1] else {
+            panic!("expected a table");
+        };
+        assert_eq!(table.token.as_deref(), Some("detailed-token"));
+        assert_eq!(table.name.as_deref(), Some("dashboard"));
+        assert_eq!(table.scopes, ["read"]);
+        let TokenConfig::Detailed(table) = &config.tokens[2] else {
+            panic!("expected a table");
+        };
+        assert_eq!(table.username.as_deref(), Some("prometheus"));
+    }
+
+    #[test]
+    fn builds_tokens_from_tables() {
+        let table = token(concat!(
+            r#"{ token = "t", name = "dashboard", client_id = "c-1", metadata = { team = "ops" }, "#,
+            r#"not_before = "2024-01-01T00:00:00Z", expires_at = "2025-01-01T00:00:00+01:00" }"#,
+        ))
+        .unwrap();
+        let identity = table.grant.identity.as_ref().unwrap();
+        assert_eq!(identity.name(), "dashboard");
+        assert_eq!(identity.client_id(), Some("c-1"));
+        assert_eq!(identity.metadata("team"), Some("ops"));
+        assert!(table.grant.not_before.is_some());
+        assert!(table.grant.expires_at.is_some());
+        let basic = token(r#"{ username = "prometheus", token = "s3cret" }"#).unwrap();
+        assert_eq!(basic.username.as_deref(), Some("prometheus"));
+    }
+
+    #[test]
+    fn reports_invalid_tables() {
+        assert_eq!(
+            token(r#"{ token = "t", hash = "sha256:00" }"#).err(),
+            Some(ConfigError::AmbiguousSecret { index: 0 })
+        );
+        assert_eq!(
+            token(r#"{ name = "dashboard" }"#).err(),
+            Some(ConfigError::MissingSecret { index: 0 })
+        );
+        assert_eq!(
+            token(r#"{ token = "t", expires_at = "tomorrow" }"#).err(),
+            Some(ConfigError::InvalidTime { index: 0 })
+        );
+        assert!(matches!(
+            token(r#"{ hash = "sha256:xyz" }"#),
+            Err(ConfigError::InvalidHash { index: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn reports_the_index_of_the_invalid_token() {
+        let figment = Figment::from(Toml::string(
+            r#"
+            [apitoken]
+            tokens = ["ok", { token = "t", expires_at = "tomorrow" }]
+            "#,
+        ));
+        let error = ApiToken::from_figment(&figment).unwrap_err();
+        assert!(error.to_string().contains("token 1:"), "{error}");
+    }
+
+    #[test]
+    #[allow(clippy::result_large_err)] // required by `Jail::expect_with`
+    fn reads_the_environment_form() {
+        Jail::expect_with(|jail| {
+            jail.set_env(
+                "ROCKET_APITOKEN",
+                r#"{enabled=true,tokens=["plaintext-token",{token="t",name="dashboard"}]}"#,
+            );
+            let figment = Figment::from(Env::prefixed("ROCKET_").global());
+            let config: ApiTokenConfig = figment.focus(CONFIG_KEY).extract()?;
+            assert!(config.enabled);
+            assert_eq!(config.tokens.len(), 2);
+            let api_token = ApiToken::from_figment(&figment)?;
+            assert!(api_token.validate().is_ok());
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,145 @@
+//! Browser sessions backed by private cookies
+
+use crate::observer::{self, Origin};
+use crate::{state_missing, ApiToken, ApiTokenError, Credentials, TokenSource};
+use rocket::http::{Cookie, CookieJar, Status};
+use rocket::request::{FromRequest, Outcome};
+use rocket::{Ignite, Request, Rocket, Sentinel};
+use std::borrow::Cow;
+use std::net::IpAddr;
+
+/// Cookie name used when no [`TokenSource::PrivateCookie`] source is configured
+pub const DEFAULT_COOKIE: &str = "apitoken";
+
+impl ApiToken {
+    /// The cookie name of the first private cookie source
+    fn cookie_name(&self) -> &str {
+        self.sources
+            .iter()
+            .find_map(|source| match source {
+                TokenSource::PrivateCookie(name) => Some(name.as_str()),
+                _ => None,
+            })
+            .unwrap_or(DEFAULT_COOKIE)
+    }
+
+    /// Remove the cookie set by [`Session::login`]
+    pub fn logout(&self, cookies: &CookieJar<'_>) {
+        cookies.remove_private(self.cookie_name().to_string());
+    }
+}
+
+/// Request guard for the login and logout routes of a browser UI
+///
+/// [`login`](Self::login) validates a token and stores it in an encrypted private
+/// cookie; once it is set, requests from the browser are authorized through the
+/// [`TokenSource::PrivateCookie`] source with the same token set as API clients. The
+/// cookie is named after the first private cookie source; launch is aborted if there is
+/// none, since the cookie would never be read.
+///
+/// Logins are subject to the [`Lockout`](crate::Lockout) of the client and reported to
+/// the observers like guard decisions, so a login route cannot be used to guess tokens
+/// unnoticed.
+///
+/// ```no_run
+/// # #[macro_use] extern crate rocket;
+/// use rocket::form::Form;
+/// use rocket::http::Status;
+/// use rocket_apitoken::Session;
+///
+/// #[post("/login", data = "<token>")]
+/// async fn login(session: Session<'_>, token: Form<String>) -> Status {
+///     match session.login(&token).await {
+///         Ok(()) => Status::NoContent,
+///         Err(error) => error.status(),
+///     }
+/// }
+///
+/// #[post("/logout")]
+/// fn logout(session: Session<'_>) {
+///     session.logout();
+/// }
+/// ```
+pub struct Session<'r> {
+    api_token: &'r ApiToken,
+    cookies: &'r CookieJar<'r>,
+    client: Option<IpAddr>,
+    origin: Origin<'r>,
+}
+
+impl Session<'_> {
+    /// Validate `token` and store it in an encrypted private cookie
+    pub async fn login(&self, token: &str) -> Result<(), ApiTokenError> {
+        let credentials = Credentials::Token(Cow::Borrowed(token));
+        let checked = self
+            .api_token
+            .check_client(self.client, Ok(credentials))
+            .await;
+        let (grant, error) = match &checked {
+            Ok((_, grant)) => (Some(&**grant), None),
+            Err(denial) => (denial.grant.as_deref(), Some(&denial.error)),
+        };
+        observer::report(self.api_token, self.origin, grant, error);
+        if let Err(denial) = checked {
+            return Err(denial.error);
+        }
+        self.cookies.add_private(Cookie::new(
+            self.api_token.cookie_name().to_string(),
+            token.to_string(),
+        ));
+        Ok(())
+    }
+
+    /// Remove the cookie set by [`login`](Self::login)
+    pub fn logout(&self) {
+        self.api_token.logout(self.cookies);
+    }
+}
+
+impl std::fmt::Debug for Session<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Session")
+            .field("client", &self.client)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Sentinel for Session<'_> {
+    fn abort(rocket: &Rocket<Ignite>) -> bool {
+        state_missing(rocket)
+            || rocket
+                .state::<ApiToken>()
+                .is_some_and(|api_token| !reads_cookies(api_token))
+    }
+}
+
+fn reads_cookies(api_token: &ApiToken) -> bool {
+    let cookie = api_token
+        .sources
+        .iter()
+        .any(|source| matches!(source, TokenSource::PrivateCookie(_)));
+    if !cookie {
+        rocket::error!(
+            "apitoken: `Session` sets a private cookie, add a `TokenSource::PrivateCookie` \
+             source to read it"
+        );
+    }
+    cookie
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Session<'r> {
+    type Error = ApiTokenError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match request.rocket().state::<ApiToken>() {
+            Some(api_token) => Outcome::Success(Session {
+                api_token,
+                cookies: request.cookies(),
+                client: api_token.lockout_client(request),
+                origin: Origin::of(request),
+            }),
+            None => Outcome::Error((Status::InternalServerError, ApiTokenError::MissingState)),
+        }
+    }
+}
@@ -0,0 +1,97 @@
+//! Authorization failure reasons
+
+use rocket::http::Status;
+use std::fmt;
+use std::time::Duration;
+
+/// Why a request failed authorization
+///
+/// This is the error type of every guard in this crate, so catchers and logging can
+/// branch on the reason. [`status`](Self::status) gives the status code the guard
+/// fails with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ApiTokenError {
+    /// No token was found in any of the configured sources
+    MissingHeader,
+    /// The `Authorization` header does not use a supported scheme, or the token is
+    /// malformed
+    MalformedScheme,
+    /// The presented token is not known
+    UnknownToken,
+    /// The presented token has expired
+    Expired,
+    /// The presented token is not valid yet
+    NotYetValid,
+    /// The presented token has been revoked
+    Revoked,
+    /// The presented token lacks the scope required by the route
+    InsufficientScope {
+        /// The missing scope
+        scope: &'static str,
+    },
+    /// The presented token has exceeded its request budget
+    RateLimited {
+        /// How long until the token may be used again
+        retry_after: Duration,
+    },
+    /// The client address is blocked after too many failed attempts
+    LockedOut {
+        /// How long until the block is lifted
+        retry_after: Duration,
+    },
+    /// No `ApiToken` is managed by Rocket
+    MissingState,
+    /// A token validator could not reach its backend
+    Unavailable,
+}
+
+impl ApiTokenError {
+    /// The status code the failed guard responds with
+    pub fn status(&self) -> Status {
+        match self {
+            ApiTokenError::MissingHeader
+            | ApiTokenError::MalformedScheme
+            | ApiTokenError::UnknownToken
+            | ApiTokenError::Expired
+            | ApiTokenError::NotYetValid
+            | ApiTokenError::Revoked => Status::Unauthorized,
+            ApiTokenError::InsufficientScope { .. } => Status::Forbidden,
+            ApiTokenError::RateLimited { .. } | ApiTokenError::LockedOut { .. } => {
+                Status::TooManyRequests
+            }
+            ApiTokenError::MissingState => Status::InternalServerError,
+            ApiTokenError::Unavailable => Status::ServiceUnavailable,
+        }
+    }
+}
+
+impl fmt::Display for ApiTokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiTokenError::MissingHeader => f.write_str("no token provided"),
+            ApiTokenError::MalformedScheme => f.write_str("unsupported authorization scheme"),
+            ApiTokenError::UnknownToken => f.write_str("invalid token"),
+            ApiTokenError::Expired => f.write_str("token expired"),
+            ApiTokenError::NotYetValid => f.write_str("token not yet valid"),
+            ApiTokenError::Revoked => f.write_str("token revoked"),
+            ApiTokenError::InsufficientScope { scope } => {
+                write!(f, "insufficient scope: `{}` required", scope)
+            }
+            ApiTokenError::RateLimited { retry_after } => {
+                write!(f, "rate limited, retry after {}s", retry_after.as_secs())
+            }
+            ApiTokenError::LockedOut { retry_after } => {
+                write!(
+                    f,
+                    "too many failed attempts, retry after {}s",
+                    retry_after.as_secs()
+                )
+            }
+            ApiTokenError::MissingState => f.write_str("token state not available"),
+            ApiTokenError::Unavailable => f.write_str("token validation unavailable"),
+        }
+    }
+}
+
+impl std::error::Error for ApiTokenError {}
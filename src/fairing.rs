@@ -0,0 +1,91 @@
+//! Fairing that installs and validates `ApiToken`
+
+use crate::rate::RateLimitState;
+use crate::{source, ApiToken};
+use rocket::data::Data;
+use rocket::fairing::{self, Fairing, Info, Kind};
+use rocket::{Build, Ignite, Request, Response, Rocket};
+
+/// Managed by the fairing to record that it is attached
+struct Attached;
+
+/// Fairing that installs `ApiToken` and aborts launch if it is misconfigured
+///
+/// If an `ApiToken` is already managed it is validated as is; otherwise one is loaded
+/// from the `apitoken` configuration section with [`ApiToken::from_figment`] and
+/// managed. Launch is aborted, with the reason logged, if loading fails or
+/// [`ApiToken::validate`] reports a problem.
+///
+/// When tokens are accepted from query parameters, the fairing also removes them from
+/// the request URI before Rocket logs the request; the request guards abort launch
+/// if it is not attached then. When a [`RateLimit`](crate::RateLimit)
+/// applies, it adds the `RateLimit-Limit`, `RateLimit-Remaining` and `RateLimit-Reset`
+/// headers to responses.
+///
+/// ```no_run
+/// # #[macro_use] extern crate rocket;
+/// use rocket_apitoken::ApiTokenFairing;
+///
+/// #[launch]
+/// fn rocket() -> _ {
+///     rocket::build().attach(ApiTokenFairing)
+/// }
+/// ```
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ApiTokenFairing;
+
+#[rocket::async_trait]
+impl Fairing for ApiTokenFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "API Token",
+            kind: Kind::Ignite | Kind::Request | Kind::Response | Kind::Singleton,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        let rocket = rocket.manage(Attached);
+        let rocket = match rocket.state::<ApiToken>() {
+            Some(_) => rocket,
+            None => match ApiToken::from_figment(rocket.figment()) {
+                Ok(api_token) => rocket.manage(api_token),
+                Err(errors) => {
+                    for error in errors {
+                        rocket::error!("apitoken: {}", error);
+                    }
+                    return Err(rocket);
+                }
+            },
+        };
+        let api_token = rocket.state::<ApiToken>().expect("managed above");
+        match api_token.validate() {
+            Ok(()) => Ok(rocket),
+            Err(error) => {
+                rocket::error!("apitoken: {}", error);
+                Err(rocket)
+            }
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        let Some(api_token) = request.rocket().state::<ApiToken>() else {
+            return;
+        };
+        source::redact_query(request, &api_token.sources);
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        if let Some(state) = RateLimitState::of(request) {
+            for header in state.headers() {
+                response.set_header(header);
+            }
+        }
+    }
+}
+
+impl ApiTokenFairing {
+    /// Whether the fairing is attached to `rocket`
+    pub(crate) fn attached(rocket: &Rocket<Ignite>) -> bool {
+        rocket.state::<Attached>().is_some()
+    }
+}
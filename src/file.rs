@@ -0,0 +1,306 @@
+//! Loading tokens from a file and reloading them when it changes
+
+use crate::store::TokenStore;
+use crate::{ApiToken, ConfigError, Token, TokenConfig};
+use figment::providers::{Format, Json, Toml};
+use figment::Figment;
+use rocket::fairing::{self, Fairing, Info, Kind};
+use rocket::serde::Deserialize;
+use rocket::tokio::{self, time};
+use rocket::{Build, Orbit, Rocket};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The shortest interval between checks of a watched token file
+const MIN_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct TokenFile {
+    #[serde(default)]
+    tokens: Vec<TokenConfig>,
+}
+
+/// Parse the contents of a token file
+///
+/// `.toml` and `.json` files hold a `tokens` list in the same format as the `apitoken`
+/// configuration section. Any other file holds one plaintext token per line; blank
+/// lines and lines starting with `#` are ignored.
+fn parse(path: &Path, contents: &str) -> Result<Vec<Token>, TokenFileError> {
+    let figment = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => Figment::from(Toml::string(contents)),
+        Some("json") => Figment::from(Json::string(contents)),
+        _ => {
+            return Ok(contents
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(Token::bearer)
+                .collect())
+        }
+    };
+    let file: TokenFile = figment
+        .extract()
+        .map_err(|error| TokenFileError::Parse(Box::new(error)))?;
+    file.tokens
+        .into_iter()
+        .enumerate()
+        .map(|(index, token)| token.into_token(index))
+        .collect::<Result<_, _>>()
+        .map_err(TokenFileError::Config)
+}
+
+/// Error loading a token file
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum TokenFileError {
+    /// The file could not be read
+    Io(std::io::Error),
+    /// The file is not valid TOML or JSON
+    Parse(Box<figment::Error>),
+    /// A token in the file is invalid
+    Config(ConfigError),
+}
+
+impl fmt::Display for TokenFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenFileError::Io(error) => write!(f, "failed to read token file: {}", error),
+            TokenFileError::Parse(error) => write!(f, "failed to parse token file: {}", error),
+            TokenFileError::Config(error) => write!(f, "invalid token file: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for TokenFileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TokenFileError::Io(error) => Some(error),
+            TokenFileError::Parse(error) => Some(error),
+            TokenFileError::Config(error) => Some(error),
+        }
+    }
+}
+
+impl ApiToken {
+    /// Create a new `ApiToken` instance from a token file
+    ///
+    /// See [`TokenFileWatcher`] for the file formats.
+    pub fn from_file(path: impl AsRef<Path>, enabled: bool) -> Result<Self, TokenFileError> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path).map_err(TokenFileError::Io)?;
+        Ok(Self::from_tokens(parse(path, &contents)?, enabled))
+    }
+
+    /// Replace the token set with the contents of a token file
+    ///
+    /// The current tokens are kept if the file cannot be loaded, holds no tokens, or
+    /// holds empty or duplicate tokens.
+    pub fn reload_from_file(&self, path: impl AsRef<Path>) -> Result<(), TokenFileError> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path).map_err(TokenFileError::Io)?;
+        self.store
+            .replace_checked(parse(path, &contents)?)
+            .map_err(TokenFileError::Config)
+    }
+}
+
+/// Fairing that loads tokens from a file and reloads them whenever it changes
+///
+/// At ignite, if no `ApiToken` is managed yet, one is loaded from the file with
+/// authorization enabled. After liftoff the file is polled and the token set is
+/// replaced with its contents, first right away and then whenever they change, which
+/// also picks up in-place updates of mounted secrets. A file that fails to load, holds
+/// no tokens (e.g. while it is being rewritten), or holds empty or duplicate tokens is
+/// logged and the previous tokens stay in effect.
+///
+/// Token files in `.toml` or `.json` format hold a `tokens` list like the `apitoken`
+/// configuration section; any other file holds one plaintext token per line, with
+/// blank lines and `#` comments ignored.
+///
+/// ```no_run
+/// # #[macro_use] extern crate rocket;
+/// use rocket_apitoken::{ApiTokenFairing, TokenFileWatcher};
+///
+/// #[launch]
+/// fn rocket() -> _ {
+///     rocket::build()
+///         .attach(TokenFileWatcher::new("/etc/secrets/tokens.toml"))
+///         .attach(ApiTokenFairing)
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct TokenFileWatcher {
+    path: PathBuf,
+    interval: Duration,
+}
+
+impl TokenFileWatcher {
+    /// Watch the token file at `path`, checking for changes every five seconds
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            interval: Duration::from_secs(5),
+        }
+    }
+
+    /// Set how often the file is checked for changes
+    ///
+    /// Intervals shorter than 100 milliseconds, including zero, are raised to 100
+    /// milliseconds.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval.max(MIN_INTERVAL);
+        self
+    }
+}
+
+async fn watch(path: PathBuf, interval: Duration, store: Arc<TokenStore>) {
+    let mut last = None;
+    let mut ticker = time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let contents = match tokio::fs::read_to_string(&path).await {
+            Ok(contents) => contents,
+            Err(error) => {
+                rocket::warn!("apitoken: {}", TokenFileError::Io(error));
+                continue;
+            }
+        };
+        let digest: [u8; 32] = Sha256::digest(contents.as_bytes()).into();
+        if last == Some(digest) {
+            continue;
+        }
+        let loaded = parse(&path, &contents).and_then(|tokens| {
+            store
+                .replace_checked(tokens)
+                .map_err(TokenFileError::Config)
+        });
+        match loaded {
+            Ok(()) => {
+                rocket::info!("apitoken: loaded tokens from {}", path.display());
+                last = Some(digest);
+            }
+            Err(error) => rocket::warn!("apitoken: {}", error),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for TokenFileWatcher {
+    fn info(&self) -> Info {
+        Info {
+            name: "API Token File Watcher",
+            kind: Kind::Ignite | Kind::Liftoff,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        if rocket.state::<ApiToken>().is_some() {
+            return Ok(rocket);
+        }
+        match ApiToken::from_file(&self.path, true) {
+            Ok(api_token) => Ok(rocket.manage(api_token)),
+            Err(error) => {
+                rocket::error!("apitoken: {}", error);
+                Err(rocket)
+            }
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let Some(api_token) = rocket.state::<ApiToken>() else {
+            return;
+        };
+        let watcher = watch(self.path.clone(), self.interval, api_token.store.clone());
+        let mut shutdown = rocket.shutdown();
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = watcher => {}
+                _ = &mut shutdown => {}
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ApiTokenError;
+
+    fn api_token(dir: &Path, name: &str, contents: &str) -> (ApiToken, PathBuf) {
+        let path = dir.join(name);
+        std::fs::write(&path, contents).unwrap();
+        (ApiToken::from_file(&path, true).unwrap(), path)
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("apitoken-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn raises_short_intervals() {
+        let watcher = TokenFileWatcher::new("tokens").interval(Duration::ZERO);
+        assert_eq!(watcher.interval, MIN_INTERVAL);
+        let watcher = TokenFileWatcher::new("tokens").interval(Duration::from_secs(1));
+        assert_eq!(watcher.interval, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn parses_plain_token_files() {
+        let tokens = parse(Path::new("tokens"), "# comment\n\nfirst\n  second  \n").unwrap();
+        assert_eq!(tokens.len(), 2);
+    }
+
+    #[test]
+    fn reloads_tokens() {
+        let (api_token, path) = api_token(&temp_dir("reload"), "tokens", "old\n");
+        std::fs::write(&path, "new\n").unwrap();
+        api_token.reload_from_file(&path).unwrap();
+        assert!(api_token.store.find(None, "new").is_ok());
+        assert!(matches!(
+            api_token.store.find(None, "old"),
+            Err(ApiTokenError::UnknownToken)
+        ));
+    }
+
+    #[test]
+    fn keeps_tokens_when_file_is_emptied() {
+        let dir = temp_dir("empty");
+        let files = [
+            ("tokens", "old\n", ["", "\n# all commented out\n"]),
+            ("tokens.toml", "tokens = [\"old\"]", ["", "tokens = []"]),
+        ];
+        for (name, contents, emptied) in files {
+            let (api_token, path) = api_token(&dir, name, contents);
+            for contents in emptied {
+                std::fs::write(&path, contents).unwrap();
+                let result = api_token.reload_from_file(&path);
+                assert!(
+                    matches!(result, Err(TokenFileError::Config(ConfigError::NoTokens))),
+                    "{name}: {contents:?}"
+                );
+                assert!(api_token.store.find(None, "old").is_ok());
+            }
+        }
+    }
+
+    #[test]
+    fn keeps_tokens_when_file_has_issues() {
+        let (api_token, path) = api_token(&temp_dir("issues"), "tokens", "old\n");
+        std::fs::write(&path, "new\nnew\n").unwrap();
+        let result = api_token.reload_from_file(&path);
+        assert!(matches!(
+            result,
+            Err(TokenFileError::Config(ConfigError::DuplicateToken {
+                index: 1
+            }))
+        ));
+        assert!(api_token.store.find(None, "old").is_ok());
+        assert!(api_token.store.find(None, "new").is_err());
+    }
+}
@@ -0,0 +1,204 @@
+//! Pre-hashed token values
+
+use sha2::{Digest as _, Sha256};
+use std::fmt;
+use std::str::FromStr;
+use subtle::{Choice, ConstantTimeEq};
+
+pub(crate) type Digest = [u8; 32];
+
+/// A token stored in hashed form
+#[derive(Clone, PartialEq, Eq)]
+pub(crate) enum Secret {
+    /// HMAC-SHA256 of the token under the key of the owning `ApiToken`
+    Keyed(Digest),
+    /// Plain SHA-256 of the token
+    Sha256(Digest),
+    /// Argon2 password hash in PHC string format
+    #[cfg(feature = "argon2")]
+    Argon2(argon2::password_hash::PasswordHashString),
+    /// Bcrypt password hash
+    #[cfg(feature = "bcrypt")]
+    Bcrypt(String),
+}
+
+/// Digests of a presented token, computed once per request
+pub(crate) struct Presented {
+    pub(crate) keyed: Digest,
+    pub(crate) sha256: Digest,
+}
+
+impl Secret {
+    /// Check the presented token against this secret, in constant time.
+    ///
+    /// Slow password hashes never match here; see [`verify`](Self::verify).
+    pub(crate) fn matches(&self, presented: &Presented) -> Choice {
+        match self {
+            Secret::Keyed(digest) => digest.ct_eq(&presented.keyed),
+            Secret::Sha256(digest) => digest.ct_eq(&presented.sha256),
+            #[cfg(feature = "argon2")]
+            Secret::Argon2(_) => Choice::from(0),
+            #[cfg(feature = "bcrypt")]
+            Secret::Bcrypt(_) => Choice::from(0),
+        }
+    }
+
+    /// Whether this is a slow password hash, which takes milliseconds to verify
+    pub(crate) fn is_slow(&self) -> bool {
+        !matches!(self, Secret::Keyed(_) | Secret::Sha256(_))
+    }
+
+    /// Verify `token` against a slow password hash
+    ///
+    /// Blocks for as long as the hash takes; call it off the async executor.
+    #[cfg(any(feature = "argon2", feature = "bcrypt"))]
+    pub(crate) fn verify(&self, token: &str) -> bool {
+        match self {
+            Secret::Keyed(_) | Secret::Sha256(_) => false,
+            #[cfg(feature = "argon2")]
+            Secret::Argon2(hash) => {
+                use argon2::PasswordVerifier;
+                argon2::Argon2::default()
+                    .verify_password(token.as_bytes(), &hash.password_hash())
+                    .is_ok()
+            }
+            #[cfg(feature = "bcrypt")]
+            Secret::Bcrypt(hash) => bcrypt::verify(token, hash).unwrap_or(false),
+        }
+    }
+}
+
+pub(crate) fn sha256(value: &str) -> Digest {
+    Sha256::digest(value.as_bytes()).into()
+}
+
+/// A pre-hashed token, typically read from configuration
+///
+/// Building an `ApiToken` from hashes means the plaintext tokens never need to be
+/// present in the process at all. SHA-256 is suitable for high-entropy generated
+/// tokens; low-entropy keys should use a slow hash (`argon2` or `bcrypt` features).
+/// Slow hashes are only checked against the HTTP Basic credentials of their username,
+/// so they must be registered with [`Token::basic_hashed`](crate::Token::basic_hashed).
+///
+/// Hashes can be parsed from strings:
+/// - `sha256:<64 hex digits>` or bare hex for SHA-256
+/// - `$argon2id$...` (PHC format) with the `argon2` feature
+/// - `$2b$...` with the `bcrypt` feature
+#[derive(Clone, PartialEq, Eq)]
+pub struct TokenHash(pub(crate) Secret);
+
+impl TokenHash {
+    /// Create a hash from a hex encoded SHA-256 digest of the token
+    pub fn sha256(hex: &str) -> Result<Self, InvalidTokenHash> {
+        let hex = hex.trim();
+        if hex.len() != 64 {
+            return Err(InvalidTokenHash("SHA-256 digest must be 64 hex digits"));
+        }
+        if !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return Err(InvalidTokenHash("invalid hex"));
+        }
+        let mut digest = [0u8; 32];
+        for (byte, pair) in digest.iter_mut().zip(hex.as_bytes().chunks(2)) {
+            let pair = std::str::from_utf8(pair).map_err(|_| InvalidTokenHash("invalid hex"))?;
+            *byte = u8::from_str_radix(pair, 16).map_err(|_| InvalidTokenHash("invalid hex"))?;
+        }
+        Ok(Self(Secret::Sha256(digest)))
+    }
+
+    /// Hash a plaintext token with SHA-256
+    ///
+    /// Useful for generating the values to put in configuration.
+    pub fn sha256_of(token: &str) -> Self {
+        Self(Secret::Sha256(sha256(token)))
+    }
+
+    /// Create a hash from an Argon2 PHC string
+    #[cfg(feature = "argon2")]
+    pub fn argon2(phc: &str) -> Result<Self, InvalidTokenHash> {
+        argon2::password_hash::PasswordHashString::new(phc)
+            .map(|hash| Self(Secret::Argon2(hash)))
+            .map_err(|_| InvalidTokenHash("invalid argon2 hash"))
+    }
+
+    /// Create a hash from a bcrypt hash string
+    #[cfg(feature = "bcrypt")]
+    pub fn bcrypt(hash: &str) -> Result<Self, InvalidTokenHash> {
+        hash.parse::<bcrypt::HashParts>()
+            .map(|_| Self(Secret::Bcrypt(hash.to_string())))
+            .map_err(|_| InvalidTokenHash("invalid bcrypt hash"))
+    }
+}
+
+impl FromStr for TokenHash {
+    type Err = InvalidTokenHash;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.starts_with("$argon2") {
+            #[cfg(feature = "argon2")]
+            return Self::argon2(s);
+            #[cfg(not(feature = "argon2"))]
+            return Err(InvalidTokenHash("argon2 support is not enabled"));
+        }
+        if s.starts_with("$2") {
+            #[cfg(feature = "bcrypt")]
+            return Self::bcrypt(s);
+            #[cfg(not(feature = "bcrypt"))]
+            return Err(InvalidTokenHash("bcrypt support is not enabled"));
+        }
+        Self::sha256(s.strip_prefix("sha256:").unwrap_or(s))
+    }
+}
+
+impl fmt::Debug for TokenHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.0 {
+            Secret::Keyed(_) => "keyed",
+            Secret::Sha256(_) => "sha256",
+            #[cfg(feature = "argon2")]
+            Secret::Argon2(_) => "argon2",
+            #[cfg(feature = "bcrypt")]
+            Secret::Bcrypt(_) => "bcrypt",
+        };
+        f.debug_tuple("TokenHash").field(&kind).finish()
+    }
+}
+
+/// Error returned when a token hash cannot be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidTokenHash(&'static str);
+
+impl fmt::Display for InvalidTokenHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid token hash: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidTokenHash {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sha256_hex() {
+        let hex = "ab".repeat(32);
+        assert!(TokenHash::sha256(&hex).unwrap().0 == Secret::Sha256([0xab; 32]));
+        assert!(format!("sha256:{}", hex.to_uppercase())
+            .parse::<TokenHash>()
+            .is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_sha256_hex() {
+        for hex in [
+            "+a".repeat(32),
+            "-a".repeat(32),
+            "ag".repeat(32),
+            "ab".repeat(31),
+            "é".repeat(32),
+        ] {
+            assert!(TokenHash::sha256(&hex).is_err(), "{hex}");
+        }
+    }
+}
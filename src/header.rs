@@ -0,0 +1,187 @@
+//! Parsing of the `Authorization` header
+
+use crate::ApiTokenError;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine as _;
+use std::borrow::Cow;
+use std::fmt;
+
+/// Credentials presented by a request
+#[non_exhaustive]
+pub enum Credentials<'r> {
+    /// A bare token, from a `Bearer` header or any other source
+    Token(Cow<'r, str>),
+    /// A username and password from a `Basic` header
+    Basic {
+        /// The username
+        username: String,
+        /// The password
+        password: String,
+    },
+}
+
+impl Credentials<'_> {
+    /// The username, for `Basic` credentials
+    pub fn username(&self) -> Option<&str> {
+        match self {
+            Credentials::Token(_) => None,
+            Credentials::Basic { username, .. } => Some(username),
+        }
+    }
+
+    /// The token, or the password of `Basic` credentials
+    pub fn secret(&self) -> &str {
+        match self {
+            Credentials::Token(token) => token,
+            Credentials::Basic { password, .. } => password,
+        }
+    }
+}
+
+impl fmt::Debug for Credentials<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Credentials::Token(_) => f.write_str("Token(<redacted>)"),
+            Credentials::Basic { username, .. } => f
+                .debug_struct("Basic")
+                .field("username", username)
+                .field("password", &"<redacted>")
+                .finish(),
+        }
+    }
+}
+
+/// Split an `Authorization` header value into its scheme and credentials
+///
+/// Surrounding whitespace and any run of whitespace between the two is ignored.
+pub(crate) fn split(value: &str) -> Option<(&str, &str)> {
+    let (scheme, credentials) = value.trim().split_once(char::is_whitespace)?;
+    let credentials = credentials.trim_start();
+    (!credentials.is_empty()).then_some((scheme, credentials))
+}
+
+/// Parse a `Bearer` or `Basic` authorization header value
+///
+/// The scheme is matched case-insensitively, as required by RFC 7235.
+pub(crate) fn parse_authorization(value: &str) -> Result<Credentials<'_>, ApiTokenError> {
+    match split(value) {
+        Some((scheme, token)) if scheme.eq_ignore_ascii_case("bearer") => {
+            Ok(Credentials::Token(Cow::Borrowed(token)))
+        }
+        Some((scheme, encoded)) if scheme.eq_ignore_ascii_case("basic") => parse_basic(encoded),
+        _ => Err(ApiTokenError::MalformedScheme),
+    }
+}
+
+/// Decode `base64(username:password)` as described in RFC 7617
+fn parse_basic(encoded: &str) -> Result<Credentials<'static>, ApiTokenError> {
+    let decoded = STANDARD
+        .decode(encoded)
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .ok_or(ApiTokenError::MalformedScheme)?;
+    let (username, password) = decoded
+        .split_once(':')
+        .ok_or(ApiTokenError::MalformedScheme)?;
+    Ok(Credentials::Basic {
+        username: username.to_string(),
+        password: password.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(value: &str) -> Option<String> {
+        match parse_authorization(value) {
+            Ok(Credentials::Token(token)) => Some(token.into_owned()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn splits_scheme_and_credentials() {
+        assert_eq!(split("Bearer abc"), Some(("Bearer", "abc")));
+        assert_eq!(split("  Bearer \t  abc  "), Some(("Bearer", "abc")));
+        assert_eq!(split("Bearer"), None);
+        assert_eq!(split("Bearer   "), None);
+        assert_eq!(split(""), None);
+    }
+
+    #[test]
+    fn matches_bearer_scheme_case_insensitively() {
+        for value in ["Bearer abc", "bearer abc", "BEARER abc", "bEaReR abc"] {
+            assert_eq!(token(value).as_deref(), Some("abc"), "{value:?}");
+        }
+    }
+
+    #[test]
+    fn ignores_extra_whitespace() {
+        assert_eq!(token("Bearer   abc").as_deref(), Some("abc"));
+        assert_eq!(token("\tBearer\tabc\t").as_deref(), Some("abc"));
+    }
+
+    #[test]
+    fn rejects_empty_or_unknown_credentials() {
+        for value in ["", "Bearer", "Bearer ", "Token abc", "abc", "Bearerabc"] {
+            assert!(
+                matches!(
+                    parse_authorization(value),
+                    Err(ApiTokenError::MalformedScheme)
+                ),
+                "{value:?}"
+            );
+        }
+    }
+
+    fn basic(value: &str) -> Result<(String, String), ApiTokenError> {
+        match parse_authorization(value)? {
+            Credentials::Basic { username, password } => Ok((username, password)),
+            Credentials::Token(_) => panic!("not Basic credentials"),
+        }
+    }
+
+    fn encode(credentials: &str) -> String {
+        format!("Basic {}", STANDARD.encode(credentials))
+    }
+
+    #[test]
+    fn decodes_basic_credentials() {
+        let decoded = basic(&encode("prometheus:s3cret")).unwrap();
+        assert_eq!(decoded, ("prometheus".to_string(), "s3cret".to_string()));
+        let decoded = basic(&encode("user:pass:with:colons")).unwrap();
+        assert_eq!(
+            decoded,
+            ("user".to_string(), "pass:with:colons".to_string())
+        );
+        let decoded = basic(&encode(":")).unwrap();
+        assert_eq!(decoded, (String::new(), String::new()));
+        let decoded = basic(&format!("basic {}", STANDARD.encode("a:b"))).unwrap();
+        assert_eq!(decoded, ("a".to_string(), "b".to_string()));
+    }
+
+    #[test]
+    fn rejects_malformed_basic_credentials() {
+        let invalid_utf8 = format!("Basic {}", STANDARD.encode([0xff, b':', 0xfe]));
+        for value in [
+            encode("no-colon"),
+            "Basic not*base64".to_string(),
+            invalid_utf8,
+        ] {
+            assert!(
+                matches!(basic(&value), Err(ApiTokenError::MalformedScheme)),
+                "{value:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn redacts_secrets_in_debug() {
+        let credentials = parse_authorization("Bearer abc").unwrap();
+        assert_eq!(format!("{:?}", credentials), "Token(<redacted>)");
+        let value = encode("user:s3cret");
+        let credentials = parse_authorization(&value).unwrap();
+        assert!(!format!("{:?}", credentials).contains("s3cret"));
+    }
+}
@@ -0,0 +1,457 @@
+//! Validation of JWT bearer tokens against local keys
+
+use crate::{ApiTokenError, Credentials, Grant, Identity, TokenValidator};
+use jsonwebtoken::jwk::{AlgorithmParameters, EllipticCurve, Jwk, PublicKeyUse};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use rocket::serde::de::DeserializeOwned;
+use rocket::serde::Deserialize;
+use serde_json::{Map, Value};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// A key that JWTs may be signed with
+#[derive(Clone)]
+pub struct JwtKey {
+    key: DecodingKey,
+    algorithm: Algorithm,
+    kid: Option<String>,
+}
+
+impl JwtKey {
+    /// A shared HMAC secret for `HS256` tokens
+    pub fn hs256(secret: &[u8]) -> Self {
+        Self::new(DecodingKey::from_secret(secret), Algorithm::HS256)
+    }
+
+    /// An RSA public key in PEM format for `RS256` tokens
+    pub fn rs256_pem(pem: &[u8]) -> Result<Self, JwtKeyError> {
+        DecodingKey::from_rsa_pem(pem)
+            .map(|key| Self::new(key, Algorithm::RS256))
+            .map_err(JwtKeyError::InvalidKey)
+    }
+
+    /// An Ed25519 public key in PEM format for `EdDSA` tokens
+    pub fn eddsa_pem(pem: &[u8]) -> Result<Self, JwtKeyError> {
+        DecodingKey::from_ed_pem(pem)
+            .map(|key| Self::new(key, Algorithm::EdDSA))
+            .map_err(JwtKeyError::InvalidKey)
+    }
+
+    /// A key from a JSON Web Key
+    ///
+    /// The algorithm is taken from the `alg` member, or inferred from the key type.
+    pub fn from_jwk(jwk: &Jwk) -> Result<Self, JwtKeyError> {
+        let algorithm = match (&jwk.common.key_algorithm, &jwk.algorithm) {
+            (Some(alg), _) => Algorithm::from_str(&alg.to_string()).ok(),
+            (None, AlgorithmParameters::RSA(_)) => Some(Algorithm::RS256),
+            (None, AlgorithmParameters::OctetKey(_)) => Some(Algorithm::HS256),
+            (None, AlgorithmParameters::OctetKeyPair(_)) => Some(Algorithm::EdDSA),
+            (None, AlgorithmParameters::EllipticCurve(params)) => match params.curve {
+                EllipticCurve::P256 => Some(Algorithm::ES256),
+                EllipticCurve::P384 => Some(Algorithm::ES384),
+                _ => None,
+            },
+        };
+        let algorithm = algorithm.ok_or(JwtKeyError::UnsupportedAlgorithm)?;
+        let key = DecodingKey::from_jwk(jwk).map_err(JwtKeyError::InvalidKey)?;
+        Ok(Self {
+            kid: jwk.common.key_id.clone(),
+            ..Self::new(key, algorithm)
+        })
+    }
+
+    fn new(key: DecodingKey, algorithm: Algorithm) -> Self {
+        Self {
+            key,
+            algorithm,
+            kid: None,
+        }
+    }
+
+    /// Only use this key for tokens whose header names key id `kid`
+    pub fn with_kid(mut self, kid: impl Into<String>) -> Self {
+        self.kid = Some(kid.into());
+        self
+    }
+}
+
+impl fmt::Debug for JwtKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JwtKey")
+            .field("algorithm", &self.algorithm)
+            .field("kid", &self.kid)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A JWK set whose keys are parsed one at a time, so that unsupported ones can be skipped
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct RawJwkSet {
+    keys: Vec<Value>,
+}
+
+/// Validates JWT bearer tokens as an alternative to the static token set
+///
+/// Tokens must carry an `exp` claim. `exp` and `nbf` are checked against the clock of
+/// the `ApiToken`, allowing for the configured leeway, and `iss` and `aud` against the
+/// accepted values when any are set. The `sub` claim becomes the identity name, the
+/// `client_id` (or `azp`) claim its client id, and the space separated `scope` claim
+/// (or the `scp` list) the granted scopes. All claims are available to handlers
+/// through [`AuthorizedAs::claims`](crate::AuthorizedAs::claims).
+///
+/// ```
+/// use rocket_apitoken::{ApiToken, JwtKey, JwtValidator};
+///
+/// let jwt = JwtValidator::new([JwtKey::hs256(b"shared-secret")])
+///     .with_issuer("https://auth.example.com")
+///     .with_audience("api");
+/// let api_token = ApiToken::new(vec![], true).with_jwt(jwt);
+/// ```
+#[derive(Debug, Clone)]
+pub struct JwtValidator {
+    keys: Vec<JwtKey>,
+    issuers: Vec<String>,
+    audiences: Vec<String>,
+    leeway: Duration,
+}
+
+impl JwtValidator {
+    /// Accept tokens signed with any of `keys`
+    pub fn new(keys: impl IntoIterator<Item = JwtKey>) -> Self {
+        Self {
+            keys: keys.into_iter().collect(),
+            issuers: Vec::new(),
+            audiences: Vec::new(),
+            leeway: Duration::from_secs(60),
+        }
+    }
+
+    /// Accept tokens signed with any key of a JWK set
+    ///
+    /// Keys meant for encryption (`"use": "enc"`) and keys whose type, curve or
+    /// algorithm is not supported are skipped, as identity providers often publish
+    /// those alongside their signing keys. Fails if no signing key is left.
+    pub fn from_jwks(jwks: &str) -> Result<Self, JwtKeyError> {
+        let jwks: RawJwkSet = serde_json::from_str(jwks).map_err(JwtKeyError::InvalidJwks)?;
+        let mut keys = Vec::new();
+        for jwk in jwks.keys {
+            let Ok(jwk) = serde_json::from_value::<Jwk>(jwk) else {
+                continue;
+            };
+            if jwk.common.public_key_use == Some(PublicKeyUse::Encryption) {
+                continue;
+            }
+            match JwtKey::from_jwk(&jwk) {
+                Ok(key) => keys.push(key),
+                Err(JwtKeyError::UnsupportedAlgorithm) => continue,
+                Err(error) => return Err(error),
+            }
+        }
+        if keys.is_empty() {
+            return Err(JwtKeyError::NoSigningKeys);
+        }
+        Ok(Self::new(keys))
+    }
+
+    /// Accept tokens signed with any key of a JWK set read from `path`
+    pub fn from_jwks_file(path: impl AsRef<Path>) -> Result<Self, JwtKeyError> {
+        Self::from_jwks(&std::fs::read_to_string(path).map_err(JwtKeyError::Io)?)
+    }
+
+    /// Also accept tokens signed with any of `keys`
+    pub fn with_keys(mut self, keys: impl IntoIterator<Item = JwtKey>) -> Self {
+        self.keys.extend(keys);
+        self
+    }
+
+    /// Accept tokens issued by `issuer`
+    ///
+    /// Can be called repeatedly to accept several issuers. Without any, `iss` is not
+    /// checked.
+    pub fn with_issuer(mut self, issuer: impl Into<String>) -> Self {
+        self.issuers.push(issuer.into());
+        self
+    }
+
+    /// Accept tokens intended for `audience`
+    ///
+    /// Can be called repeatedly to accept several audiences. Without any, tokens
+    /// carrying an `aud` claim are rejected.
+    pub fn with_audience(mut self, audience: impl Into<String>) -> Self {
+        self.audiences.push(audience.into());
+        self
+    }
+
+    /// Allow for clock skew when checking `exp` and `nbf`; defaults to 60 seconds
+    pub fn with_leeway(mut self, leeway: Duration) -> Self {
+        self.leeway = leeway;
+        self
+    }
+
+    /// Verify `token` and build its grant
+    ///
+    /// The validity window is left to [`Grant::check_validity`] so that it follows the
+    /// clock of the `ApiToken`.
+    fn verify(&self, token: &str) -> Result<Arc<Grant>, ApiTokenError> {
+        let header = jsonwebtoken::decode_header(token).map_err(|_| ApiTokenError::UnknownToken)?;
+        let claims = self
+            .keys
+            .iter()
+            .filter(|key| key.algorithm == header.alg)
+            .filter(|key| match (&key.kid, &header.kid) {
+                (Some(kid), Some(wanted)) => kid == wanted,
+                _ => true,
+            })
+            .find_map(|key| {
+                jsonwebtoken::decode::<Map<String, Value>>(token, &key.key, &self.validation(key))
+                    .ok()
+            })
+            .ok_or(ApiTokenError::UnknownToken)?
+            .claims;
+        self.grant(Claims(claims)).map(Arc::new)
+    }
+
+    fn validation(&self, key: &JwtKey) -> Validation {
+        let mut validation = Validation::new(key.algorithm);
+        validation.validate_exp = false;
+        validation.validate_nbf = false;
+        if !self.issuers.is_empty() {
+            validation.set_issuer(&self.issuers);
+        }
+        if !self.audiences.is_empty() {
+            validation.set_audience(&self.audiences);
+        }
+        validation
+    }
+
+    /// Build the grant of verified `claims`
+    ///
+    /// `exp` and `nbf` are NumericDates and may be fractional. Tokens where either is
+    /// present but not a non-negative number, or is beyond the range of `SystemTime`
+    /// with the leeway applied, are rejected.
+    fn grant(&self, claims: Claims) -> Result<Grant, ApiTokenError> {
+        let time = |name| -> Result<Option<SystemTime>, ApiTokenError> {
+            let Some(value) = claims.get(name) else {
+                return Ok(None);
+            };
+            value
+                .as_f64()
+                .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+                .and_then(|since_epoch| SystemTime::UNIX_EPOCH.checked_add(since_epoch))
+                .map(Some)
+                .ok_or(ApiTokenError::UnknownToken)
+        };
+        let not_before = time("nbf")?.map(|nbf| {
+            nbf.checked_sub(self.leeway)
+                .unwrap_or(SystemTime::UNIX_EPOCH)
+        });
+        let expires_at = match time("exp")? {
+            Some(exp) => Some(
+                exp.checked_add(self.leeway)
+                    .ok_or(ApiTokenError::UnknownToken)?,
+            ),
+            None => None,
+        };
+        let identity = claims.subject().map(|subject| {
+            let identity = Identity::new(subject);
+            match claims.string("client_id").or_else(|| claims.string("azp")) {
+                Some(client_id) => identity.with_client_id(client_id),
+                None => identity,
+            }
+        });
+        let scopes = match (claims.get("scope"), claims.get("scp")) {
+            (Some(Value::String(scope)), _) => scope.split_whitespace().map(Into::into).collect(),
+            (_, Some(Value::Array(scp))) => scp
+                .iter()
+                .filter_map(Value::as_str)
+                .map(Into::into)
+                .collect(),
+            (_, Some(Value::String(scp))) => scp.split_whitespace().map(Into::into).collect(),
+            _ => Default::default(),
+        };
+        Ok(Grant {
+            identity,
+            scopes,
+            not_before,
+            expires_at,
+            rate_limit: None,
+            claims: Some(claims),
+        })
+    }
+}
+
+#[rocket::async_trait]
+impl TokenValidator for JwtValidator {
+    async fn validate(&self, credentials: &Credentials<'_>) -> Result<Arc<Grant>, ApiTokenError> {
+        match credentials {
+            Credentials::Token(token) => self.verify(token),
+            _ => Err(ApiTokenError::UnknownToken),
+        }
+    }
+}
+
+/// The claims of a validated JWT
+#[derive(Debug, Clone, Default)]
+pub struct Claims(Map<String, Value>);
+
+impl Claims {
+    /// Look up a claim
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.0.get(name)
+    }
+
+    /// The `sub` claim
+    pub fn subject(&self) -> Option<&str> {
+        self.string("sub")
+    }
+
+    /// All claims
+    pub fn all(&self) -> &Map<String, Value> {
+        &self.0
+    }
+
+    /// Deserialize the claims into an application defined type
+    pub fn deserialize<T: DeserializeOwned>(&self) -> Result<T, serde_json::Error> {
+        T::deserialize(&self.0)
+    }
+
+    fn string(&self, name: &str) -> Option<&str> {
+        self.get(name).and_then(Value::as_str)
+    }
+}
+
+/// Error loading a JWT verification key
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum JwtKeyError {
+    /// The JWK set file could not be read
+    Io(std::io::Error),
+    /// The key could not be parsed
+    InvalidKey(jsonwebtoken::errors::Error),
+    /// The JWK set is not valid JSON
+    InvalidJwks(serde_json::Error),
+    /// The algorithm of a JSON Web Key is missing and cannot be inferred, or is not
+    /// supported
+    UnsupportedAlgorithm,
+    /// The JWK set contains no supported signing key
+    NoSigningKeys,
+}
+
+impl fmt::Display for JwtKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JwtKeyError::Io(error) => write!(f, "failed to read JWK set: {}", error),
+            JwtKeyError::InvalidKey(error) => write!(f, "invalid JWT key: {}", error),
+            JwtKeyError::InvalidJwks(error) => write!(f, "invalid JWK set: {}", error),
+            JwtKeyError::UnsupportedAlgorithm => f.write_str("unsupported JWT key algorithm"),
+            JwtKeyError::NoSigningKeys => f.write_str("JWK set contains no supported signing key"),
+        }
+    }
+}
+
+impl std::error::Error for JwtKeyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            JwtKeyError::Io(error) => Some(error),
+            JwtKeyError::InvalidKey(error) => Some(error),
+            JwtKeyError::InvalidJwks(error) => Some(error),
+            JwtKeyError::UnsupportedAlgorithm | JwtKeyError::NoSigningKeys => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{EncodingKey, Header};
+    use serde_json::json;
+
+    const SECRET: &[u8] = b"test-secret";
+
+    fn sign(claims: Value) -> String {
+        jsonwebtoken::encode(
+            &Header::new(Algorithm::HS256),
+            &claims,
+            &EncodingKey::from_secret(SECRET),
+        )
+        .unwrap()
+    }
+
+    fn verify(claims: Value) -> Result<Arc<Grant>, ApiTokenError> {
+        JwtValidator::new([JwtKey::hs256(SECRET)]).verify(&sign(claims))
+    }
+
+    #[test]
+    fn accepts_valid_token() {
+        let grant = verify(json!({ "sub": "alice", "exp": 4_000_000_000u64 })).unwrap();
+        assert_eq!(grant.identity.as_ref().unwrap().name(), "alice");
+        assert_eq!(
+            grant.expires_at,
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(4_000_000_060))
+        );
+    }
+
+    #[test]
+    fn rejects_out_of_range_times() {
+        let result = verify(json!({ "exp": u64::MAX }));
+        assert!(matches!(result, Err(ApiTokenError::UnknownToken)));
+        let result = verify(json!({ "exp": 4_000_000_000u64, "nbf": u64::MAX }));
+        assert!(matches!(result, Err(ApiTokenError::UnknownToken)));
+    }
+
+    #[test]
+    fn skips_unusable_jwks_keys() {
+        let jwks = json!({ "keys": [
+            { "kty": "RSA", "use": "enc", "alg": "RSA-OAEP", "kid": "enc", "n": "AQAB", "e": "AQAB" },
+            { "kty": "EC", "crv": "secp256k1", "kid": "k1", "x": "AA", "y": "AA" },
+            { "kty": "EC", "crv": "P-521", "kid": "p521", "x": "AA", "y": "AA" },
+            { "kty": "oct", "alg": "HS256", "kid": "sig", "k": "dGVzdC1zZWNyZXQ" },
+        ]});
+        let validator = JwtValidator::from_jwks(&jwks.to_string()).unwrap();
+        assert_eq!(validator.keys.len(), 1);
+        assert_eq!(validator.keys[0].kid.as_deref(), Some("sig"));
+        assert!(validator
+            .verify(&sign(json!({ "exp": 4_000_000_000u64 })))
+            .is_ok());
+    }
+
+    #[test]
+    fn rejects_jwks_without_signing_keys() {
+        let jwks = json!({ "keys": [
+            { "kty": "RSA", "use": "enc", "alg": "RSA-OAEP", "n": "AQAB", "e": "AQAB" },
+        ]});
+        let result = JwtValidator::from_jwks(&jwks.to_string());
+        assert!(matches!(result, Err(JwtKeyError::NoSigningKeys)));
+    }
+
+    #[test]
+    fn accepts_fractional_times() {
+        let grant = verify(json!({ "exp": 1000.5, "nbf": 100.25 })).unwrap();
+        assert_eq!(
+            grant.expires_at,
+            Some(SystemTime::UNIX_EPOCH + Duration::from_millis(1_060_500))
+        );
+        assert_eq!(
+            grant.not_before,
+            Some(SystemTime::UNIX_EPOCH + Duration::from_millis(40_250))
+        );
+        assert!(matches!(
+            grant.check_validity(SystemTime::now()),
+            Err(ApiTokenError::Expired)
+        ));
+    }
+
+    #[test]
+    fn rejects_malformed_times() {
+        for exp in [json!(-1), json!("4000000000"), json!(null), json!(true)] {
+            let result = verify(json!({ "exp": exp }));
+            assert!(matches!(result, Err(ApiTokenError::UnknownToken)), "{exp}");
+        }
+        let result = verify(json!({ "exp": 4_000_000_000u64, "nbf": "soon" }));
+        assert!(matches!(result, Err(ApiTokenError::UnknownToken)));
+    }
+}
@@ -6,11 +6,12 @@
 //!
 //! # Usage Example
 //! ```no_run
-//! use rocket;
+//! #[macro_use]
+//! extern crate rocket;
 //! use rocket_apitoken::{ApiToken, Authorized};
 //!
-//! #[post("/<method>?<json>", data = "<data>")]
-//! async fn protected_endpoint(_auth: Authorized, /* other params */) {
+//! #[post("/<method>")]
+//! async fn protected_endpoint(_auth: Authorized, method: &str /* other params */) {
 //!     // If this executes, the request was authorized
 //!     // ...
 //! }
@@ -26,78 +27,665 @@
 //!
 //! # Configuration
 //! - Create an `ApiToken` instance with a list of valid tokens and enabled state
-//! - Add it to Rocket's state using `.manage()`
+//! - Add it to Rocket's state using `.manage()`, or attach `ApiTokenFairing` to load it from
+//!   configuration and abort launch if it is misconfigured
 //! - Use the `Authorized` guard in your route handlers
+//! - Use the `AuthorizedAs` guard instead when the handler needs the `Identity` attached to
+//!   the token (see `Token` and `ApiToken::from_tokens`)
+//! - Use the `RequireScope<S>` guard to restrict a route to tokens granted scope `S`;
+//!   valid tokens without the scope get 403 Forbidden
+//! - Register `rocket_apitoken::catchers()` to answer failures with RFC 6750
+//!   `WWW-Authenticate` challenges, plus a `Basic` one when Basic users exist; set the
+//!   realm with `ApiToken::with_realm`
+//! - Accept tokens from other places, such as an `X-API-Key` header, with
+//!   `ApiToken::with_sources`. Query parameters such as `?access_token=` for download links
+//!   are opt-in (`TokenSource::access_token()`) and require `ApiTokenFairing`, which keeps
+//!   them out of request logs
+//! - With the `cookies` feature, browser sessions can share the same tokens: `Session::login`
+//!   validates a token and stores it in a private cookie read by `TokenSource::PrivateCookie`
+//! - Tools that only speak HTTP Basic, such as Prometheus scrapers, can send
+//!   `Authorization: Basic`; register their users with `Token::basic`, or set `username` on a
+//!   configured token
+//! - With the `jwt` feature, `ApiToken::with_jwt` (or a `jwt` table in the configuration)
+//!   also accepts JWTs signed with local HS256, RS256 or EdDSA keys or a JWK set file;
+//!   `AuthorizedAs::claims` gives handlers their claims
+//! - Back validation with your own store (SQL, Redis, an internal service) by implementing
+//!   `TokenValidator` and adding it with `ApiToken::with_validator`; it is asked about
+//!   credentials that are not in the built-in token set
+//! - With the `sqlite` or `postgres` feature, `SqlTokenStore` is a ready-made validator that
+//!   reads tokens from an `api_tokens` table through a `sqlx` pool, such as the one of a
+//!   `rocket_db_pools` database; the schema ships in `migrations/`
+//! - Wrap slow validators in `CachedValidator` to remember their results across requests for
+//!   a bounded time; within a request, all guards already share one validation
+//! - Give each token or identity a request budget with `ApiToken::with_rate_limit` (or
+//!   `Token::rate_limit`); requests over budget get 429 Too Many Requests with `Retry-After`
+//!   and `RateLimit-*` headers
+//! - Slow down key guessing with `ApiToken::with_lockout`: client addresses with too many failed
+//!   attempts are blocked for exponentially growing periods, reported to an `on_lockout` hook
+//! - Feed audit logs with `ApiToken::with_observer`: every guard decision is reported as an
+//!   `AuthEvent` with its outcome, reason, identity, route, method and client address
+//! - With the `metrics` feature, mount `rocket_apitoken::metrics_routes()` to serve Prometheus
+//!   counters of guard outcomes, failure reasons and per-identity usage, and a latency histogram
+//! - With the `tracing` feature, validation runs in an `apitoken.authorize` span recording the
+//!   outcome, identity and failure reason, and each guard decision is logged as an event; the
+//!   `Authorization` value is always redacted
 //!
-//! When enabled, requests must include a valid token in the Authorization header.
+//! When enabled, requests must include a valid token from one of the configured sources
+//! (the Authorization header by default).
 //! When disabled, all requests are authorized automatically.
+//! Failing guards report the reason as an `ApiTokenError`, which also gives the status code.
+//!
+//! Tokens are never kept in memory as plaintext. Each one is stored as an HMAC-SHA256
+//! digest under a key generated when the `ApiToken` is created, and incoming tokens are
+//! compared against those digests in constant time.
+//!
+//! Alternatively, build the `ApiToken` from pre-hashed values with `ApiToken::from_hashes`
+//! so the plaintext tokens never reach the process. SHA-256 hashes are always supported;
+//! Argon2 and bcrypt hashes for low-entropy passwords are available with the `argon2` and
+//! `bcrypt` features; they are only verified for the HTTP Basic username they belong to
+//! (`Token::basic_hashed`), off the async executor.
+//!
+//! # Loading from configuration
+//! Instead of building the token list in code, `ApiToken::from_figment` reads it from the
+//! `apitoken` section of Rocket's configuration, so keys can be rotated without a rebuild:
+//!
+//! ```toml
+//! [default.apitoken]
+//! enabled = true
+//! sources = ["authorization", { header = "X-API-Key" }]
+//! tokens = [
+//!     "plaintext-token",
+//!     { hash = "sha256:9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08", name = "dashboard", scopes = ["read"] },
+//! ]
+//! ```
+//!
+//! The same settings can be given through the environment, e.g.
+//! `ROCKET_APITOKEN='{enabled=true,tokens=["plaintext-token"]}'`.
+//!
+//! ```no_run
+//! # #[macro_use] extern crate rocket;
+//! # use rocket_apitoken::ApiToken;
+//! #[launch]
+//! fn rocket() -> _ {
+//!     let rocket = rocket::build();
+//!     let api_token = ApiToken::from_figment(rocket.figment()).expect("invalid apitoken config");
+//!     rocket.manage(api_token)
+//! }
+//! ```
+//!
+//! Tokens can also be read from a file with `ApiToken::from_file`. Attaching a
+//! `TokenFileWatcher` loads the file at ignite and reloads it whenever its contents
+//! change, which suits secrets mounted from a secrets manager.
+//!
+//! # Rotating tokens at runtime
+//! The token set held by a managed `ApiToken` can be changed while Rocket is running with
+//! `add`, `revoke` and `replace_all`, e.g. from an admin route. The token is taken from
+//! the request body rather than the path, so that it does not end up in access logs:
+//!
+//! ```no_run
+//! # #[macro_use] extern crate rocket;
+//! # use rocket::{http::Status, State};
+//! # use rocket_apitoken::{ApiToken, RequireScope, Scope};
+//! # struct Admin;
+//! # impl Scope for Admin { const NAME: &'static str = "admin"; }
+//! #[post("/tokens/revoke", data = "<token>")]
+//! fn revoke(_auth: RequireScope<Admin>, api_token: &State<ApiToken>, token: &str) -> Status {
+//!     if api_token.revoke(token) {
+//!         Status::NoContent
+//!     } else {
+//!         Status::NotFound
+//!     }
+//! }
+//! ```
 
 #![warn(missing_docs)]
 
-use rocket::http::Status;
+use challenge::Failure;
+use lockout::LockoutTracker;
+use rate::RateLimiter;
 use rocket::request::{FromRequest, Outcome};
-use rocket::Request;
-use std::collections::HashSet;
+use rocket::{Ignite, Request, Rocket, Sentinel};
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::Arc;
+use store::TokenStore;
+
+mod cache;
+mod challenge;
+mod clock;
+mod config;
+#[cfg(feature = "cookies")]
+mod cookie;
+mod error;
+mod fairing;
+mod file;
+mod hash;
+mod header;
+#[cfg(feature = "jwt")]
+mod jwt;
+mod lockout;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod observer;
+mod rate;
+mod scope;
+mod source;
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+mod sql;
+mod store;
+mod token;
+#[cfg(feature = "tracing")]
+mod trace;
+mod validator;
+
+pub use cache::{CacheStats, CachedValidator};
+pub use challenge::{catchers, Challenge};
+pub use clock::{Clock, SystemClock};
+#[cfg(feature = "jwt")]
+pub use config::JwtConfig;
+pub use config::{ApiTokenConfig, ConfigError, TokenConfig, TokenTable, CONFIG_KEY};
+#[cfg(feature = "cookies")]
+pub use cookie::{Session, DEFAULT_COOKIE};
+pub use error::ApiTokenError;
+pub use fairing::ApiTokenFairing;
+pub use file::{TokenFileError, TokenFileWatcher};
+pub use hash::{InvalidTokenHash, TokenHash};
+pub use header::Credentials;
+#[cfg(feature = "jwt")]
+pub use jwt::{Claims, JwtKey, JwtKeyError, JwtValidator};
+pub use lockout::{Lockout, LockoutEvent};
+#[cfg(feature = "metrics")]
+pub use metrics::metrics_routes;
+pub use observer::{AuthEvent, AuthObserver, AuthOutcome};
+pub use rate::RateLimit;
+pub use scope::{RequireScope, Scope};
+pub use source::TokenSource;
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+pub use sql::{SqlTokenStore, SQL_MIGRATION};
+pub use token::{Grant, Identity, Token};
+pub use validator::TokenValidator;
 
 /// Configuration for API token authorization
+///
+/// The token set can be changed at any time through a shared reference, e.g. from a
+/// handler taking `&State<ApiToken>` or a background task, without restarting Rocket.
+/// Requests in flight keep using the snapshot they started with.
 pub struct ApiToken {
-    tokens: HashSet<String>,
+    store: Arc<TokenStore>,
     enabled: bool,
+    issues: Vec<ConfigError>,
+    clock: Arc<dyn Clock>,
+    realm: Option<String>,
+    sources: Vec<TokenSource>,
+    validators: Vec<Arc<dyn TokenValidator>>,
+    rate_limit: Option<RateLimit>,
+    limiter: RateLimiter,
+    lockout: Option<LockoutTracker>,
+    observers: Vec<Arc<dyn AuthObserver>>,
+    #[cfg(feature = "metrics")]
+    metrics: metrics::Metrics,
 }
 
 impl ApiToken {
     /// Create a new `ApiToken` instance
     pub fn new(tokens: Vec<String>, enabled: bool) -> Self {
+        Self::from_tokens(tokens.into_iter().map(Token::bearer), enabled)
+    }
+
+    /// Create a new `ApiToken` instance from pre-hashed tokens
+    ///
+    /// No plaintext token is ever held by the returned instance.
+    pub fn from_hashes(hashes: Vec<TokenHash>, enabled: bool) -> Self {
+        Self::from_tokens(hashes.into_iter().map(Token::hashed), enabled)
+    }
+
+    /// Create a new `ApiToken` instance from tokens carrying identities
+    ///
+    /// Empty and duplicate tokens are accepted here but reported by
+    /// [`validate`](Self::validate).
+    pub fn from_tokens(tokens: impl IntoIterator<Item = Token>, enabled: bool) -> Self {
+        let store = TokenStore::new();
+        let (entries, issues) = store.prepare(tokens);
+        store.store(entries);
         Self {
-            tokens: tokens
-                .into_iter()
-                .map(|token| format!("Bearer {}", token))
-                .collect(),
+            store: Arc::new(store),
             enabled,
+            issues,
+            clock: Arc::new(SystemClock),
+            realm: None,
+            sources: vec![TokenSource::Authorization],
+            validators: Vec::new(),
+            rate_limit: None,
+            limiter: RateLimiter::new(),
+            lockout: None,
+            observers: Vec::new(),
+            #[cfg(feature = "metrics")]
+            metrics: Default::default(),
         }
     }
 
+    /// Set where the token is read from, in order of preference
+    ///
+    /// By default only the `Authorization` header is used.
+    pub fn with_sources(mut self, sources: Vec<TokenSource>) -> Self {
+        self.sources = sources;
+        self
+    }
+
+    /// Set the realm announced in `WWW-Authenticate` challenges
+    ///
+    /// See [`catchers`].
+    pub fn with_realm(mut self, realm: impl Into<String>) -> Self {
+        self.realm = Some(realm.into());
+        self
+    }
+
+    /// Ask `validator` about credentials that are not in the token set
+    ///
+    /// Validators are asked in the order they were added. See [`TokenValidator`].
+    pub fn with_validator(mut self, validator: impl TokenValidator) -> Self {
+        self.validators.push(Arc::new(validator));
+        self
+    }
+
+    /// Also accept JWT bearer tokens verified by `jwt`
+    ///
+    /// Tokens are looked up in the static token set first.
+    #[cfg(feature = "jwt")]
+    pub fn with_jwt(self, jwt: JwtValidator) -> Self {
+        self.with_validator(jwt)
+    }
+
+    /// Limit the requests made with each token or identity
+    ///
+    /// Tokens registered with their own [`RateLimit`] use that instead. Requests over
+    /// budget fail with [`ApiTokenError::RateLimited`] (429 Too Many Requests); register
+    /// [`catchers`] to send `Retry-After`, and attach [`ApiTokenFairing`] to send the
+    /// `RateLimit-*` headers on every response.
+    pub fn with_rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limit = Some(limit);
+        self
+    }
+
+    /// Block client addresses that keep presenting invalid credentials
+    ///
+    /// See [`Lockout`].
+    pub fn with_lockout(mut self, lockout: Lockout) -> Self {
+        self.lockout = Some(LockoutTracker::new(lockout));
+        self
+    }
+
+    /// Report every decision of the request guards to `observer`
+    ///
+    /// Can be called repeatedly; observers are called in registration order.
+    pub fn with_observer(mut self, observer: impl AuthObserver) -> Self {
+        self.observers.push(Arc::new(observer));
+        self
+    }
+
+    /// Set the clock used to check token validity windows
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Check the token set for misconfiguration
+    ///
+    /// Fails if authorization is enabled without any tokens or validators, or if the
+    /// tokens this instance was created with contained empty or duplicate entries.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if let Some(issue) = self.issues.first() {
+            return Err(issue.clone());
+        }
+        if self.enabled && self.store.is_empty() && self.validators.is_empty() {
+            return Err(ConfigError::NoTokens);
+        }
+        Ok(())
+    }
+
     /// Add bearer tokens to the list of valid tokens
-    pub fn add_bearer(&mut self, token: &str) {
-        self.tokens.insert(format!("Bearer {}", token));
+    pub fn add_bearer(&self, token: &str) {
+        self.add(Token::bearer(token));
+    }
+
+    /// Add a pre-hashed token to the list of valid tokens
+    pub fn add_hash(&self, hash: TokenHash) {
+        self.add(Token::hashed(hash));
+    }
+
+    /// Add a token to the list of valid tokens
+    ///
+    /// Adding a token that is already present replaces its identity and scopes.
+    pub fn add(&self, token: Token) {
+        self.store.add(token);
+    }
+
+    /// Remove a token from the list of valid tokens
+    ///
+    /// Returns whether the token was present. Works for tokens added in plaintext as
+    /// well as pre-hashed ones. Later use of the token fails with
+    /// [`ApiTokenError::Revoked`] until it is added again or the set is replaced.
+    /// HTTP Basic credentials are revoked by username with [`revoke_user`](Self::revoke_user).
+    ///
+    /// Results cached by validators for the token are dropped as well, so revoking a
+    /// token in a validator's backend takes effect once this is called.
+    pub fn revoke(&self, token: &str) -> bool {
+        let credentials = Credentials::Token(token.into());
+        for validator in &self.validators {
+            validator.invalidate(&credentials);
+        }
+        self.store.revoke(token)
+    }
+
+    /// Remove the HTTP Basic credentials of `username`
+    ///
+    /// Returns whether the user was present. Since the password is not known, all
+    /// results cached by validators are dropped.
+    pub fn revoke_user(&self, username: &str) -> bool {
+        for validator in &self.validators {
+            validator.invalidate_all();
+        }
+        self.store.revoke_user(username)
+    }
+
+    /// Remove a pre-hashed token from the list of valid tokens
+    ///
+    /// Returns whether the hash was present.
+    pub fn revoke_hash(&self, hash: &TokenHash) -> bool {
+        self.store.revoke_secret(&hash.0)
+    }
+
+    /// Replace the whole token set at once
+    ///
+    /// Requests see either the old set or the new one, never a mix of both.
+    pub fn replace_all(&self, tokens: impl IntoIterator<Item = Token>) {
+        self.store.replace_all(tokens);
+    }
+
+    /// The authorization metrics in the Prometheus text exposition format
+    ///
+    /// See [`metrics_routes`] for the metrics and a ready-made route.
+    #[cfg(feature = "metrics")]
+    pub fn render_metrics(&self) -> String {
+        self.metrics.render()
+    }
+
+    async fn authorize(&self, request: &Request<'_>) -> AuthResult {
+        if !self.enabled {
+            return Ok(None);
+        }
+        let credentials = self
+            .sources
+            .iter()
+            .find_map(|source| source.extract(request))
+            .ok_or(ApiTokenError::MissingHeader)
+            .and_then(|credentials| credentials);
+        let client = self.lockout_client(request);
+        let (credentials, grant) = self.check_client(client, credentials).await?;
+        if let Some(limit) = grant.rate_limit.or(self.rate_limit) {
+            self.limiter
+                .acquire(request, &credentials, &grant, limit, self.clock.now())
+                .map_err(Denial::of(&grant))?;
+        }
+        Ok(Some(grant))
     }
+
+    /// The address `request` is tracked under for lockouts, if lockouts are enabled
+    fn lockout_client(&self, request: &Request<'_>) -> Option<IpAddr> {
+        self.lockout.as_ref()?.client(request)
+    }
+
+    /// Check the `credentials` presented by `client`, subject to its lockout
+    async fn check_client<'c>(
+        &self,
+        client: Option<IpAddr>,
+        credentials: Result<Credentials<'c>, ApiTokenError>,
+    ) -> Result<(Credentials<'c>, Arc<Grant>), Denial> {
+        let lockout = self.lockout.as_ref().zip(client);
+        if let Some((lockout, ip)) = lockout {
+            lockout.check(ip, self.clock.now())?;
+        }
+        let checked = match credentials {
+            Ok(credentials) => self
+                .check(&credentials)
+                .await
+                .map(|grant| (credentials, grant)),
+            Err(error) => Err(error.into()),
+        };
+        if let (Some((lockout, ip)), Err(denial)) = (lockout, &checked) {
+            lockout.record(ip, &denial.error, self.clock.now());
+        }
+        checked
+    }
+
+    /// Validate the credentials and check that they are currently valid
+    async fn check(&self, credentials: &Credentials<'_>) -> Result<Arc<Grant>, Denial> {
+        let mut result = self.store.validate(credentials).await;
+        for validator in &self.validators {
+            match result {
+                Err(ApiTokenError::UnknownToken) => result = validator.validate(credentials).await,
+                _ => break,
+            }
+        }
+        let grant = result?;
+        grant
+            .check_validity(self.clock.now())
+            .map_err(Denial::of(&grant))?;
+        Ok(grant)
+    }
+}
+
+impl fmt::Debug for ApiToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ApiToken")
+            .field("enabled", &self.enabled)
+            .field("tokens", &self.store.len())
+            .field("issues", &self.issues)
+            .field("realm", &self.realm)
+            .field("sources", &self.sources)
+            .field("validators", &self.validators.len())
+            .field("rate_limit", &self.rate_limit)
+            .field(
+                "lockout",
+                &self.lockout.as_ref().map(LockoutTracker::policy),
+            )
+            .field("observers", &self.observers.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// The grant of the presented token, or `None` when authorization is disabled
+pub(crate) type AuthResult = Result<Option<Arc<Grant>>, Denial>;
+
+/// A failed authorization, along with the grant of the credentials if they were found
+///
+/// Credentials can be recognized and still be refused, e.g. when they expired or are
+/// over their rate limit; observers are told whose they were.
+#[derive(Debug, Clone)]
+pub(crate) struct Denial {
+    pub(crate) error: ApiTokenError,
+    pub(crate) grant: Option<Arc<Grant>>,
+}
+
+impl Denial {
+    fn of(grant: &Arc<Grant>) -> impl FnOnce(ApiTokenError) -> Self + '_ {
+        move |error| Self {
+            error,
+            grant: Some(grant.clone()),
+        }
+    }
+}
+
+impl From<ApiTokenError> for Denial {
+    fn from(error: ApiTokenError) -> Self {
+        Self { error, grant: None }
+    }
+}
+
+/// The outcome of authorizing a request, cached so that every guard on a route shares it
+struct Authorization(AuthResult);
+
+pub(crate) async fn authorize<'r>(request: &'r Request<'_>) -> &'r AuthResult {
+    &request
+        .local_cache_async(async {
+            Authorization(match request.rocket().state::<ApiToken>() {
+                Some(token) => {
+                    #[cfg(feature = "metrics")]
+                    let started = std::time::Instant::now();
+                    #[cfg(feature = "tracing")]
+                    let span = trace::span(request);
+                    let authorization = token.authorize(request);
+                    #[cfg(feature = "tracing")]
+                    let authorization =
+                        tracing::Instrument::instrument(authorization, span.clone());
+                    let result = authorization.await;
+                    #[cfg(feature = "metrics")]
+                    token.metrics.observe_duration(started.elapsed());
+                    #[cfg(feature = "tracing")]
+                    trace::record(&span, &result);
+                    result
+                }
+                None => Err(ApiTokenError::MissingState.into()),
+            })
+        })
+        .await
+        .0
+}
+
+/// Let a guard through, reporting it to the observers
+pub(crate) fn succeed<S>(
+    request: &Request<'_>,
+    grant: Option<&Grant>,
+    guard: S,
+) -> Outcome<S, ApiTokenError> {
+    observer::observe(request, grant, None);
+    Outcome::Success(guard)
+}
+
+/// Fail a guard with `error`, recording it for the catchers and reporting it to the
+/// observers
+pub(crate) fn fail<S>(
+    request: &Request<'_>,
+    grant: Option<&Grant>,
+    error: &ApiTokenError,
+) -> Outcome<S, ApiTokenError> {
+    observer::observe(request, grant, Some(error));
+    request.local_cache(|| Failure(Some(error.clone())));
+    Outcome::Error((error.status(), error.clone()))
 }
 
 /// Request guard that ensures requests are authorized
 ///
 /// This guard will succeed if either:
 /// - Authorization is disabled (`enabled = false` in ApiToken)
-/// - A valid bearer token is provided in the Authorization header
+/// - A valid token or set of Basic credentials is provided by one of the configured sources
 ///
 /// # Errors
-/// Returns 401 Unauthorized if:
-/// - Authorization is enabled and no Authorization header is present
-/// - The provided token is invalid
+/// Fails with an [`ApiTokenError`]; its [`status`](ApiTokenError::status) is
+/// 401 Unauthorized if:
+/// - Authorization is enabled and no credentials are present
+/// - The provided token is invalid, expired, not yet valid or revoked
 #[derive(Debug)]
 pub struct Authorized;
 
+/// Aborts launch when no `ApiToken` is managed, instead of failing at request time
+pub(crate) fn state_missing(rocket: &Rocket<Ignite>) -> bool {
+    rocket.state::<ApiToken>().is_none()
+}
+
+/// Aborts launch when no `ApiToken` is managed, or when it reads tokens from query
+/// parameters without [`ApiTokenFairing`] to keep them out of the request logs
+pub(crate) fn cannot_authorize(rocket: &Rocket<Ignite>) -> bool {
+    state_missing(rocket)
+        || rocket
+            .state::<ApiToken>()
+            .is_some_and(|api_token| logs_query_tokens(api_token, rocket))
+}
+
+fn logs_query_tokens(api_token: &ApiToken, rocket: &Rocket<Ignite>) -> bool {
+    let query = api_token
+        .sources
+        .iter()
+        .any(|source| matches!(source, TokenSource::Query(_)));
+    if query && !ApiTokenFairing::attached(rocket) {
+        rocket::error!(
+            "apitoken: tokens are read from query parameters, attach `ApiTokenFairing` to \
+             keep them out of the request logs"
+        );
+        return true;
+    }
+    false
+}
+
+impl Sentinel for Authorized {
+    fn abort(rocket: &Rocket<Ignite>) -> bool {
+        cannot_authorize(rocket)
+    }
+}
+
 #[rocket::async_trait]
 impl<'r> FromRequest<'r> for Authorized {
-    type Error = &'static str;
+    type Error = ApiTokenError;
 
     async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
-        let token = request
-            .rocket()
-            .state::<ApiToken>()
-            .expect("Token state not available.");
-        if !token.enabled {
-            return Outcome::Success(Authorized);
+        match authorize(request).await {
+            Ok(grant) => succeed(request, grant.as_deref(), Authorized),
+            Err(denial) => fail(request, denial.grant.as_deref(), &denial.error),
         }
-        match request.headers().get_one("Authorization") {
-            Some(value) => {
-                // Check the Bearer token
-                if token.tokens.contains(value) {
-                    Outcome::Success(Authorized)
-                } else {
-                    Outcome::Error((Status::Unauthorized, "invalid token"))
-                }
-            }
-            _ => Outcome::Error((Status::Unauthorized, "Authorization header not found")),
+    }
+}
+
+/// Request guard that authorizes the request and exposes the caller's identity
+///
+/// Succeeds and fails exactly like [`Authorized`].
+#[derive(Debug, Clone, Copy)]
+pub struct AuthorizedAs<'r> {
+    grant: Option<&'r Grant>,
+}
+
+impl<'r> AuthorizedAs<'r> {
+    /// The identity attached to the presented token
+    ///
+    /// Returns `None` when authorization is disabled or the token was registered
+    /// without an identity.
+    pub fn identity(&self) -> Option<&'r Identity> {
+        self.grant.and_then(|grant| grant.identity.as_ref())
+    }
+
+    /// Check whether the presented token has been granted `scope`
+    ///
+    /// Always true when authorization is disabled.
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.grant.is_none_or(|grant| grant.scopes.contains(scope))
+    }
+
+    /// The claims of the presented token, if it was a JWT
+    #[cfg(feature = "jwt")]
+    pub fn claims(&self) -> Option<&'r Claims> {
+        self.grant.and_then(|grant| grant.claims.as_ref())
+    }
+}
+
+impl Sentinel for AuthorizedAs<'_> {
+    fn abort(rocket: &Rocket<Ignite>) -> bool {
+        cannot_authorize(rocket)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AuthorizedAs<'r> {
+    type Error = ApiTokenError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match authorize(request).await {
+            Ok(grant) => succeed(
+                request,
+                grant.as_deref(),
+                AuthorizedAs {
+                    grant: grant.as_deref(),
+                },
+            ),
+            Err(denial) => fail(request, denial.grant.as_deref(), &denial.error),
         }
     }
 }
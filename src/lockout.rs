@@ -0,0 +1,409 @@
+//! Blocking clients that keep presenting invalid credentials
+
+use crate::ApiTokenError;
+use rocket::serde::Deserialize;
+use rocket::Request;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// Number of tracked clients above which stale ones are dropped
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// Most clients tracked at once; the ones quiet the longest are dropped beyond it
+const MAX_CLIENTS: usize = 100_000;
+
+/// The longest a lockout lasts, whatever the policy
+const MAX_DURATION: Duration = Duration::from_secs(100 * 365 * 24 * 60 * 60);
+
+type Hook = Arc<dyn Fn(&LockoutEvent) + Send + Sync>;
+
+/// A lockout, as reported to the [`on_lockout`](Lockout::on_lockout) hook
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct LockoutEvent {
+    /// The blocked client address; for IPv6 clients, the address of their /64 network
+    pub ip: IpAddr,
+    /// How many times in a row the client has been locked out, starting at 1
+    pub lockouts: u32,
+    /// How long the client is blocked for
+    pub duration: Duration,
+}
+
+/// Policy for blocking client addresses after repeated failed attempts
+///
+/// After `threshold` failed attempts from one client address, further requests from it
+/// fail with [`ApiTokenError::LockedOut`] (429 Too Many Requests) without their
+/// credentials being checked. The first lockout lasts
+/// `duration`, and each one following it without a quiet `window` in between twice
+/// as long as the previous one, up to `max_duration`. Unknown, revoked and malformed
+/// credentials count as failed attempts; missing ones do not. Successful requests do
+/// not reset the count: failures and lockouts are only forgotten after a `window`
+/// without failures.
+///
+/// Clients are identified by the address of the connection
+/// ([`Request::remote`](rocket::Request::remote)). Behind a reverse proxy that is the
+/// proxy's address; use [`trust_ip_header`](Self::trust_ip_header) there instead.
+/// IPv6 clients are grouped by /64 network, since a single host usually holds a whole
+/// one. At most 100000 clients are tracked at once, dropping the ones quiet the longest
+/// beyond that, and lockouts last at most 100 years.
+///
+/// In configuration, a policy is written as
+/// `{ threshold = 5, duration = 30, max_duration = 3600, window = 900 }` with times in
+/// seconds; all but `threshold` are optional, and `trust_ip_header = true` may be added.
+///
+/// ```
+/// use rocket_apitoken::{ApiToken, Lockout};
+///
+/// let lockout = Lockout::new(5).on_lockout(|event| {
+///     eprintln!("blocked {} for {:?}", event.ip, event.duration);
+/// });
+/// let api_token = ApiToken::new(vec!["secret-token".to_string()], true).with_lockout(lockout);
+/// ```
+#[derive(Clone, Deserialize)]
+#[serde(crate = "rocket::serde", from = "LockoutTable")]
+pub struct Lockout {
+    threshold: u32,
+    duration: Duration,
+    max_duration: Duration,
+    window: Duration,
+    trust_ip_header: bool,
+    hook: Option<Hook>,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct LockoutTable {
+    threshold: u32,
+    duration: Option<u64>,
+    max_duration: Option<u64>,
+    window: Option<u64>,
+    #[serde(default)]
+    trust_ip_header: bool,
+}
+
+impl From<LockoutTable> for Lockout {
+    fn from(table: LockoutTable) -> Self {
+        let mut lockout = Lockout::new(table.threshold);
+        if let Some(secs) = table.duration {
+            lockout = lockout.with_duration(Duration::from_secs(secs));
+        }
+        if let Some(secs) = table.max_duration {
+            lockout = lockout.with_max_duration(Duration::from_secs(secs));
+        }
+        if let Some(secs) = table.window {
+            lockout = lockout.with_window(Duration::from_secs(secs));
+        }
+        if table.trust_ip_header {
+            lockout = lockout.trust_ip_header();
+        }
+        lockout
+    }
+}
+
+impl Lockout {
+    /// Block a client address after `threshold` failed attempts
+    ///
+    /// By default the first lockout lasts 30 seconds, lockouts last at most an hour,
+    /// and a client is forgotten after 15 minutes without failed attempts.
+    pub fn new(threshold: u32) -> Self {
+        Self {
+            threshold,
+            duration: Duration::from_secs(30),
+            max_duration: Duration::from_secs(60 * 60),
+            window: Duration::from_secs(15 * 60),
+            trust_ip_header: false,
+            hook: None,
+        }
+    }
+
+    /// Set how long the first lockout lasts
+    pub fn with_duration(mut self, duration: Duration) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    /// Set the longest a lockout may last
+    pub fn with_max_duration(mut self, max_duration: Duration) -> Self {
+        self.max_duration = max_duration;
+        self
+    }
+
+    /// Set how long a client must stay quiet before its failures are forgotten
+    pub fn with_window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// Identify clients by [`Request::client_ip`](rocket::Request::client_ip) instead of
+    /// the connection address
+    ///
+    /// `client_ip` honors Rocket's `ip_header` setting, `X-Real-IP` by default, which any
+    /// client can set to an arbitrary address to dodge its lockout or to lock out
+    /// someone else. Only use this behind a reverse proxy that overwrites that header.
+    pub fn trust_ip_header(mut self) -> Self {
+        self.trust_ip_header = true;
+        self
+    }
+
+    /// Call `hook` whenever a client address is locked out
+    pub fn on_lockout(mut self, hook: impl Fn(&LockoutEvent) + Send + Sync + 'static) -> Self {
+        self.hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// The duration of the `lockouts`-th lockout in a row
+    fn backoff(&self, lockouts: u32) -> Duration {
+        let factor = 1u32
+            .checked_shl(lockouts.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        self.duration
+            .checked_mul(factor)
+            .unwrap_or(Duration::MAX)
+            .min(self.max_duration)
+            .min(MAX_DURATION)
+    }
+}
+
+impl fmt::Debug for Lockout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Lockout")
+            .field("threshold", &self.threshold)
+            .field("duration", &self.duration)
+            .field("max_duration", &self.max_duration)
+            .field("window", &self.window)
+            .field("trust_ip_header", &self.trust_ip_header)
+            .field("hook", &self.hook.is_some())
+            .finish()
+    }
+}
+
+#[derive(Default)]
+struct Attempts {
+    failures: u32,
+    lockouts: u32,
+    last_failure: Option<SystemTime>,
+    locked_until: Option<SystemTime>,
+}
+
+impl Attempts {
+    /// When the client last failed or was last blocked until
+    fn quiet_since(&self) -> Option<SystemTime> {
+        self.locked_until.max(self.last_failure)
+    }
+
+    fn is_stale(&self, now: SystemTime, window: Duration) -> bool {
+        self.quiet_since()
+            .is_none_or(|since| now.duration_since(since).unwrap_or_default() >= window)
+    }
+}
+
+/// The failed attempts of each client, pruned once they grow past `prune_at`
+struct Clients {
+    attempts: HashMap<IpAddr, Attempts>,
+    prune_at: usize,
+}
+
+impl Default for Clients {
+    fn default() -> Self {
+        Self {
+            attempts: HashMap::new(),
+            prune_at: PRUNE_THRESHOLD,
+        }
+    }
+}
+
+impl Clients {
+    /// The attempts of `ip`, making room for it if it is new
+    fn entry(&mut self, ip: IpAddr, now: SystemTime, window: Duration) -> &mut Attempts {
+        if !self.attempts.contains_key(&ip) && self.attempts.len() >= self.prune_at {
+            self.prune(now, window);
+        }
+        self.attempts.entry(ip).or_default()
+    }
+
+    /// Drop stale clients, and the ones quiet the longest while there are too many
+    ///
+    /// The next pruning waits for the number of clients to double, or to reach the
+    /// limit, so that its cost is spread over the insertions in between.
+    fn prune(&mut self, now: SystemTime, window: Duration) {
+        self.attempts
+            .retain(|_, attempts| !attempts.is_stale(now, window));
+        let keep = MAX_CLIENTS * 9 / 10;
+        if self.attempts.len() > keep {
+            let mut quiet: Vec<_> = self
+                .attempts
+                .iter()
+                .map(|(ip, attempts)| (attempts.quiet_since(), *ip))
+                .collect();
+            let excess = quiet.len() - keep;
+            quiet.select_nth_unstable(excess - 1);
+            for (_, ip) in &quiet[..excess] {
+                self.attempts.remove(ip);
+            }
+        }
+        self.prune_at = (self.attempts.len() * 2).clamp(PRUNE_THRESHOLD, MAX_CLIENTS);
+    }
+}
+
+/// The failed attempts of each client address
+pub(crate) struct LockoutTracker {
+    policy: Lockout,
+    clients: Mutex<Clients>,
+}
+
+impl LockoutTracker {
+    pub(crate) fn new(policy: Lockout) -> Self {
+        Self {
+            policy,
+            clients: Mutex::default(),
+        }
+    }
+
+    pub(crate) fn policy(&self) -> &Lockout {
+        &self.policy
+    }
+
+    fn clients(&self) -> std::sync::MutexGuard<'_, Clients> {
+        self.clients.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The address `request` is tracked under, if it is known
+    pub(crate) fn client(&self, request: &Request<'_>) -> Option<IpAddr> {
+        let ip = match self.policy.trust_ip_header {
+            true => request.client_ip(),
+            false => request.remote().map(|remote| remote.ip()),
+        };
+        ip.map(network)
+    }
+
+    /// Fail if `ip` is currently locked out
+    pub(crate) fn check(&self, ip: IpAddr, now: SystemTime) -> Result<(), ApiTokenError> {
+        let clients = self.clients();
+        match clients
+            .attempts
+            .get(&ip)
+            .and_then(|attempts| attempts.locked_until)
+        {
+            Some(until) if until > now => Err(ApiTokenError::LockedOut {
+                retry_after: until.duration_since(now).unwrap_or_default(),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Record that checking the credentials presented by `ip` failed with `error`
+    ///
+    /// Successful attempts are deliberately not recorded: otherwise any valid token
+    /// would reset the count, and guesses interleaved with it would never lock out.
+    pub(crate) fn record(&self, ip: IpAddr, error: &ApiTokenError, now: SystemTime) {
+        let failed = matches!(
+            error,
+            ApiTokenError::UnknownToken | ApiTokenError::Revoked | ApiTokenError::MalformedScheme
+        );
+        if !failed {
+            return;
+        }
+        let mut clients = self.clients();
+        let entry = clients.entry(ip, now, self.policy.window);
+        if entry.is_stale(now, self.policy.window) {
+            *entry = Attempts::default();
+        }
+        entry.failures += 1;
+        entry.last_failure = Some(now);
+        if entry.failures < self.policy.threshold {
+            return;
+        }
+        entry.failures = 0;
+        entry.lockouts += 1;
+        let duration = self.policy.backoff(entry.lockouts);
+        let Some(until) = now.checked_add(duration) else {
+            return;
+        };
+        entry.locked_until = Some(until);
+        let event = LockoutEvent {
+            ip,
+            lockouts: entry.lockouts,
+            duration,
+        };
+        drop(clients);
+        rocket::warn!("apitoken: locked out {} for {}s", ip, duration.as_secs());
+        if let Some(hook) = &self.policy.hook {
+            hook(&event);
+        }
+    }
+}
+
+/// The address a client is tracked under: IPv4 as is, IPv6 by its /64 network
+fn network(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(_) => ip,
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => IpAddr::V6((u128::from(v6) & !u128::from(u64::MAX)).into()),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+    use std::time::UNIX_EPOCH;
+
+    fn now() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_000_000)
+    }
+
+    #[test]
+    fn locks_out_for_unbounded_durations() {
+        let tracker = LockoutTracker::new(
+            Lockout::new(1)
+                .with_duration(Duration::MAX)
+                .with_max_duration(Duration::MAX),
+        );
+        let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        for _ in 0..3 {
+            tracker.record(ip, &ApiTokenError::UnknownToken, now());
+            let later = now() + Duration::from_secs(50 * 365 * 24 * 60 * 60);
+            assert!(matches!(
+                tracker.check(ip, later),
+                Err(ApiTokenError::LockedOut { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn groups_ipv6_clients_by_network() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        assert_eq!(network(ip("2001:db8:1:2:aaaa::1")), ip("2001:db8:1:2::"));
+        assert_eq!(
+            network(ip("2001:db8:1:2:aaaa::1")),
+            network(ip("2001:db8:1:2:bbbb::2"))
+        );
+        assert_ne!(
+            network(ip("2001:db8:1:2::1")),
+            network(ip("2001:db8:1:3::1"))
+        );
+        assert_eq!(network(ip("::ffff:192.0.2.1")), ip("192.0.2.1"));
+        assert_eq!(network(ip("192.0.2.1")), ip("192.0.2.1"));
+    }
+
+    #[test]
+    fn bounds_the_number_of_tracked_clients() {
+        let tracker = LockoutTracker::new(Lockout::new(2));
+        let blocked = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        for _ in 0..2 {
+            tracker.record(blocked, &ApiTokenError::UnknownToken, now());
+        }
+        for i in 0..(MAX_CLIENTS as u128 + 10) {
+            let ip = IpAddr::V6(Ipv6Addr::from(i << 64));
+            let now = now() + Duration::from_micros(i as u64);
+            tracker.record(ip, &ApiTokenError::UnknownToken, now);
+            assert!(tracker.clients().attempts.len() <= MAX_CLIENTS);
+        }
+        assert!(tracker.check(blocked, now()).is_err());
+    }
+}
@@ -0,0 +1,300 @@
+//! Prometheus metrics of authorization outcomes
+
+use crate::{ApiToken, ApiTokenError, AuthOutcome, Identity};
+use rocket::http::ContentType;
+use rocket::{Route, State};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Upper bounds of the authorization latency histogram, in seconds
+const BUCKETS: [f64; 12] = [
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 1.0,
+];
+
+/// Number of identities tracked by name; further ones are counted together
+const MAX_IDENTITIES: usize = 1_000;
+
+/// Label of the identities beyond [`MAX_IDENTITIES`]
+const OTHER_IDENTITIES: &str = "_other";
+
+/// The counters and histogram of an `ApiToken`
+#[derive(Default)]
+pub(crate) struct Metrics {
+    counters: Mutex<Counters>,
+}
+
+#[derive(Default)]
+struct Counters {
+    outcomes: BTreeMap<&'static str, u64>,
+    failures: BTreeMap<&'static str, u64>,
+    identities: BTreeMap<String, u64>,
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Metrics {
+    fn counters(&self) -> std::sync::MutexGuard<'_, Counters> {
+        self.counters
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Count a guard decision
+    pub(crate) fn record(
+        &self,
+        outcome: AuthOutcome,
+        reason: Option<&ApiTokenError>,
+        identity: Option<&Identity>,
+    ) {
+        let mut counters = self.counters();
+        *counters.outcomes.entry(outcome.as_str()).or_default() += 1;
+        if let Some(reason) = reason {
+            *counters.failures.entry(reason_label(reason)).or_default() += 1;
+        }
+        if let (AuthOutcome::Allowed, Some(identity)) = (outcome, identity) {
+            let name = match counters.identities.len() < MAX_IDENTITIES
+                || counters.identities.contains_key(identity.name())
+            {
+                true => identity.name(),
+                false => OTHER_IDENTITIES,
+            };
+            *counters.identities.entry(name.to_string()).or_default() += 1;
+        }
+    }
+
+    /// Record how long authorizing a request took
+    pub(crate) fn observe_duration(&self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        let mut counters = self.counters();
+        for (bucket, bound) in counters.buckets.iter_mut().zip(BUCKETS) {
+            if secs <= bound {
+                *bucket += 1;
+            }
+        }
+        counters.sum += secs;
+        counters.count += 1;
+    }
+
+    /// The metrics in the Prometheus text exposition format
+    pub(crate) fn render(&self) -> String {
+        let counters = self.counters();
+        let mut out = String::new();
+        out.push_str("# HELP apitoken_decisions_total Request guard decisions by outcome.\n");
+        out.push_str("# TYPE apitoken_decisions_total counter\n");
+        for (outcome, count) in &counters.outcomes {
+            let _ = writeln!(
+                out,
+                "apitoken_decisions_total{{outcome=\"{outcome}\"}} {count}"
+            );
+        }
+        out.push_str("# HELP apitoken_failures_total Denied request guard decisions by reason.\n");
+        out.push_str("# TYPE apitoken_failures_total counter\n");
+        for (reason, count) in &counters.failures {
+            let _ = writeln!(
+                out,
+                "apitoken_failures_total{{reason=\"{reason}\"}} {count}"
+            );
+        }
+        out.push_str("# HELP apitoken_identity_requests_total Allowed requests by identity.\n");
+        out.push_str("# TYPE apitoken_identity_requests_total counter\n");
+        for (identity, count) in &counters.identities {
+            let _ = writeln!(
+                out,
+                "apitoken_identity_requests_total{{identity=\"{}\"}} {count}",
+                escape(identity)
+            );
+        }
+        out.push_str(
+            "# HELP apitoken_authorization_duration_seconds Time spent authorizing a request.\n",
+        );
+        out.push_str("# TYPE apitoken_authorization_duration_seconds histogram\n");
+        for (bound, count) in BUCKETS.iter().zip(counters.buckets) {
+            let _ = writeln!(
+                out,
+                "apitoken_authorization_duration_seconds_bucket{{le=\"{bound}\"}} {count}"
+            );
+        }
+        let _ = writeln!(
+            out,
+            "apitoken_authorization_duration_seconds_bucket{{le=\"+Inf\"}} {}",
+            counters.count
+        );
+        let _ = writeln!(
+            out,
+            "apitoken_authorization_duration_seconds_sum {}",
+            counters.sum
+        );
+        let _ = writeln!(
+            out,
+            "apitoken_authorization_duration_seconds_count {}",
+            counters.count
+        );
+        out
+    }
+}
+
+/// A stable label for the reason of a failure
+fn reason_label(error: &ApiTokenError) -> &'static str {
+    match error {
+        ApiTokenError::MissingHeader => "missing_credentials",
+        ApiTokenError::MalformedScheme => "malformed",
+        ApiTokenError::UnknownToken => "unknown_token",
+        ApiTokenError::Expired => "expired",
+        ApiTokenError::NotYetValid => "not_yet_valid",
+        ApiTokenError::Revoked => "revoked",
+        ApiTokenError::InsufficientScope { .. } => "insufficient_scope",
+        ApiTokenError::RateLimited { .. } => "rate_limited",
+        ApiTokenError::LockedOut { .. } => "locked_out",
+        ApiTokenError::MissingState => "missing_state",
+        ApiTokenError::Unavailable => "unavailable",
+    }
+}
+
+/// Escape a label value
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[rocket::get("/metrics")]
+fn metrics(api_token: &State<ApiToken>) -> (ContentType, String) {
+    let content_type =
+        ContentType::new("text", "plain").with_params([("version", "0.0.4"), ("charset", "utf-8")]);
+    (content_type, api_token.render_metrics())
+}
+
+/// A `GET /metrics` route serving the metrics of the managed `ApiToken`
+///
+/// The route is not protected; mount it where only the scraper can reach it, or serve
+/// [`ApiToken::render_metrics`] from a handler with its own guard instead. The metrics
+/// are:
+/// - `apitoken_decisions_total{outcome}`: guard decisions, `allowed`, `denied` or
+///   `bypassed`
+/// - `apitoken_failures_total{reason}`: denials by reason, such as `unknown_token` or
+///   `expired`
+/// - `apitoken_identity_requests_total{identity}`: allowed requests by identity name; the
+///   identities beyond the first 1000 are counted as `_other`
+/// - `apitoken_authorization_duration_seconds`: a histogram of the time spent validating
+///   credentials, once per request
+///
+/// ```no_run
+/// # #[macro_use] extern crate rocket;
+/// # use rocket_apitoken::ApiTokenFairing;
+/// #[launch]
+/// fn rocket() -> _ {
+///     rocket::build()
+///         .attach(ApiTokenFairing)
+///         .mount("/internal", rocket_apitoken::metrics_routes())
+/// }
+/// ```
+pub fn metrics_routes() -> Vec<Route> {
+    rocket::routes![metrics]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(metrics: &Metrics, name: &str) -> Vec<String> {
+        metrics
+            .render()
+            .lines()
+            .filter(|line| line.starts_with(name))
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn renders_decisions_and_failures() {
+        let metrics = Metrics::default();
+        let identity = Identity::new("dashboard");
+        metrics.record(AuthOutcome::Allowed, None, Some(&identity));
+        metrics.record(AuthOutcome::Allowed, None, Some(&identity));
+        metrics.record(AuthOutcome::Denied, Some(&ApiTokenError::Expired), None);
+        metrics.record(AuthOutcome::Bypassed, None, None);
+        assert_eq!(
+            lines(&metrics, "apitoken_decisions_total"),
+            [
+                "apitoken_decisions_total{outcome=\"allowed\"} 2",
+                "apitoken_decisions_total{outcome=\"bypassed\"} 1",
+                "apitoken_decisions_total{outcome=\"denied\"} 1",
+            ]
+        );
+        assert_eq!(
+            lines(&metrics, "apitoken_failures_total"),
+            ["apitoken_failures_total{reason=\"expired\"} 1"]
+        );
+        assert_eq!(
+            lines(&metrics, "apitoken_identity_requests_total"),
+            ["apitoken_identity_requests_total{identity=\"dashboard\"} 2"]
+        );
+    }
+
+    #[test]
+    fn renders_cumulative_histogram() {
+        let metrics = Metrics::default();
+        metrics.observe_duration(Duration::from_micros(50));
+        metrics.observe_duration(Duration::from_millis(3));
+        metrics.observe_duration(Duration::from_secs(2));
+        let buckets = lines(&metrics, "apitoken_authorization_duration_seconds_bucket");
+        assert_eq!(buckets.len(), BUCKETS.len() + 1);
+        assert_eq!(
+            buckets[0],
+            "apitoken_authorization_duration_seconds_bucket{le=\"0.0001\"} 1"
+        );
+        assert_eq!(
+            buckets[4],
+            "apitoken_authorization_duration_seconds_bucket{le=\"0.0025\"} 1"
+        );
+        assert_eq!(
+            buckets[5],
+            "apitoken_authorization_duration_seconds_bucket{le=\"0.005\"} 2"
+        );
+        assert_eq!(
+            buckets[11],
+            "apitoken_authorization_duration_seconds_bucket{le=\"1\"} 2"
+        );
+        assert_eq!(
+            buckets[12],
+            "apitoken_authorization_duration_seconds_bucket{le=\"+Inf\"} 3"
+        );
+        assert_eq!(
+            lines(&metrics, "apitoken_authorization_duration_seconds_count"),
+            ["apitoken_authorization_duration_seconds_count 3"]
+        );
+    }
+
+    #[test]
+    fn counts_identities_beyond_the_limit_together() {
+        let metrics = Metrics::default();
+        for i in 0..MAX_IDENTITIES + 2 {
+            let identity = Identity::new(format!("client-{i}"));
+            metrics.record(AuthOutcome::Allowed, None, Some(&identity));
+        }
+        let identity = Identity::new("client-0");
+        metrics.record(AuthOutcome::Allowed, None, Some(&identity));
+        let identities = lines(&metrics, "apitoken_identity_requests_total");
+        assert_eq!(identities.len(), MAX_IDENTITIES + 1);
+        assert!(
+            identities.contains(&"apitoken_identity_requests_total{identity=\"_other\"} 2".into())
+        );
+        assert!(identities
+            .contains(&"apitoken_identity_requests_total{identity=\"client-0\"} 2".into()));
+    }
+
+    #[test]
+    fn escapes_identity_labels() {
+        let metrics = Metrics::default();
+        let identity = Identity::new("a\"b\\c\nd");
+        metrics.record(AuthOutcome::Allowed, None, Some(&identity));
+        assert_eq!(
+            lines(&metrics, "apitoken_identity_requests_total"),
+            ["apitoken_identity_requests_total{identity=\"a\\\"b\\\\c\\nd\"} 1"]
+        );
+    }
+}
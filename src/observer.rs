@@ -0,0 +1,152 @@
+//! Notification of authorization decisions
+
+use crate::{ApiToken, ApiTokenError, Grant, Identity};
+use rocket::http::Method;
+use rocket::Request;
+use std::fmt;
+use std::net::IpAddr;
+
+/// Receives an [`AuthEvent`] for every decision of the request guards
+///
+/// Register observers with [`ApiToken::with_observer`]. They are called synchronously
+/// on the request path, so slow sinks (files, network) should hand events off to a
+/// channel or background task. Any `Fn(&AuthEvent)` closure is an observer.
+///
+/// ```
+/// use rocket_apitoken::{ApiToken, AuthEvent};
+///
+/// let api_token = ApiToken::new(vec!["secret-token".to_string()], true).with_observer(
+///     |event: &AuthEvent<'_>| {
+///         eprintln!(
+///             "{} {} {} from {:?}: {} ({:?})",
+///             event.method,
+///             event.route.unwrap_or("-"),
+///             event.identity.map_or("-", |identity| identity.name()),
+///             event.client_ip,
+///             event.outcome,
+///             event.reason.map(ToString::to_string),
+///         );
+///     },
+/// );
+/// ```
+pub trait AuthObserver: Send + Sync + 'static {
+    /// Handle one decision
+    fn observe(&self, event: &AuthEvent<'_>);
+}
+
+impl<F: Fn(&AuthEvent<'_>) + Send + Sync + 'static> AuthObserver for F {
+    fn observe(&self, event: &AuthEvent<'_>) {
+        self(event)
+    }
+}
+
+/// The decision of a request guard
+///
+/// Never contains the presented credentials, only the identity they map to.
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub struct AuthEvent<'a> {
+    /// Whether the request was let through
+    pub outcome: AuthOutcome,
+    /// Why the request was denied
+    pub reason: Option<&'a ApiTokenError>,
+    /// The identity of the presented token, if it was recognized and has one
+    pub identity: Option<&'a Identity>,
+    /// The URI pattern of the matched route, e.g. `/items/<id>`
+    pub route: Option<&'a str>,
+    /// The request method
+    pub method: Method,
+    /// The client address, as reported by [`Request::client_ip`]
+    pub client_ip: Option<IpAddr>,
+}
+
+/// Whether a request guard let the request through
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum AuthOutcome {
+    /// Valid credentials were presented
+    Allowed,
+    /// The request was rejected; see [`AuthEvent::reason`]
+    Denied,
+    /// Authorization is disabled, so no credentials were checked
+    Bypassed,
+}
+
+impl AuthOutcome {
+    /// The outcome in lowercase, e.g. `"denied"`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuthOutcome::Allowed => "allowed",
+            AuthOutcome::Denied => "denied",
+            AuthOutcome::Bypassed => "bypassed",
+        }
+    }
+}
+
+impl fmt::Display for AuthOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Where a request came from and which route it matched, for [`AuthEvent`]s
+#[derive(Clone, Copy)]
+pub(crate) struct Origin<'r> {
+    route: Option<&'r str>,
+    method: Method,
+    client_ip: Option<IpAddr>,
+}
+
+impl<'r> Origin<'r> {
+    pub(crate) fn of(request: &'r Request<'_>) -> Self {
+        Self {
+            route: request.route().map(|route| route.uri.as_str()),
+            method: request.method(),
+            client_ip: request.client_ip(),
+        }
+    }
+}
+
+/// Report the decision of a guard on `request` to the observers of its `ApiToken`
+pub(crate) fn observe(
+    request: &Request<'_>,
+    grant: Option<&Grant>,
+    reason: Option<&ApiTokenError>,
+) {
+    if let Some(api_token) = request.rocket().state::<ApiToken>() {
+        report(api_token, Origin::of(request), grant, reason);
+    }
+}
+
+/// Report a decision on a request from `origin` to the observers of `api_token`
+pub(crate) fn report(
+    api_token: &ApiToken,
+    origin: Origin<'_>,
+    grant: Option<&Grant>,
+    reason: Option<&ApiTokenError>,
+) {
+    let outcome = match (reason, grant) {
+        (Some(_), _) => AuthOutcome::Denied,
+        (None, Some(_)) => AuthOutcome::Allowed,
+        (None, None) => AuthOutcome::Bypassed,
+    };
+    let identity = grant.and_then(|grant| grant.identity.as_ref());
+    #[cfg(feature = "metrics")]
+    api_token.metrics.record(outcome, reason, identity);
+    #[cfg(feature = "tracing")]
+    crate::trace::decision(outcome, reason, identity);
+    if api_token.observers.is_empty() {
+        return;
+    }
+    let event = AuthEvent {
+        outcome,
+        reason,
+        identity,
+        route: origin.route,
+        method: origin.method,
+        client_ip: origin.client_ip,
+    };
+    for observer in &api_token.observers {
+        observer.observe(&event);
+    }
+}
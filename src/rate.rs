@@ -0,0 +1,246 @@
+//! Per-token request budgets
+
+use crate::hash::Digest;
+use crate::{ApiTokenError, Credentials, Grant};
+use hmac::{Hmac, Mac};
+use rocket::http::Header;
+use rocket::serde::Deserialize;
+use rocket::Request;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+/// Number of buckets above which full (idle) buckets are dropped
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// A request budget: bursts of up to `requests`, replenished over `period`
+///
+/// Budgets are token buckets kept per identity name, or per token for tokens without
+/// an identity. Set a default with [`ApiToken::with_rate_limit`](crate::ApiToken::with_rate_limit)
+/// and override it for single tokens with [`Token::rate_limit`](crate::Token::rate_limit).
+/// In configuration, a budget is written as `{ requests = 100, period = 60 }` with the
+/// period in seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(crate = "rocket::serde", try_from = "RateLimitTable")]
+pub struct RateLimit {
+    requests: u32,
+    period: Duration,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct RateLimitTable {
+    requests: u32,
+    period: u64,
+}
+
+impl TryFrom<RateLimitTable> for RateLimit {
+    type Error = &'static str;
+
+    fn try_from(table: RateLimitTable) -> Result<Self, Self::Error> {
+        match (table.requests, table.period) {
+            (0, _) => Err("rate limit `requests` must be at least 1"),
+            (_, 0) => Err("rate limit `period` must be at least 1 second"),
+            (requests, period) => Ok(RateLimit::new(requests, Duration::from_secs(period))),
+        }
+    }
+}
+
+impl RateLimit {
+    /// Allow bursts of `requests`, replenished evenly over `period`
+    ///
+    /// # Panics
+    /// If `requests` or `period` is zero.
+    pub fn new(requests: u32, period: Duration) -> Self {
+        assert!(requests > 0, "rate limit must allow at least one request");
+        assert!(!period.is_zero(), "rate limit period must not be zero");
+        Self { requests, period }
+    }
+
+    /// Allow `requests` per second
+    ///
+    /// # Panics
+    /// If `requests` is zero.
+    pub fn per_second(requests: u32) -> Self {
+        Self::new(requests, Duration::from_secs(1))
+    }
+
+    /// Allow `requests` per minute
+    ///
+    /// # Panics
+    /// If `requests` is zero.
+    pub fn per_minute(requests: u32) -> Self {
+        Self::new(requests, Duration::from_secs(60))
+    }
+
+    /// Requests replenished per second
+    fn rate(&self) -> f64 {
+        self.requests as f64 / self.period.as_secs_f64()
+    }
+}
+
+/// The budget state of the current request, for the `RateLimit-*` response headers
+#[derive(Clone, Copy)]
+pub(crate) struct RateLimitState {
+    limit: u32,
+    remaining: u32,
+    reset: Duration,
+}
+
+impl RateLimitState {
+    /// The `RateLimit-Limit`, `RateLimit-Remaining` and `RateLimit-Reset` headers
+    pub(crate) fn headers(&self) -> [Header<'static>; 3] {
+        [
+            Header::new("RateLimit-Limit", self.limit.to_string()),
+            Header::new("RateLimit-Remaining", self.remaining.to_string()),
+            Header::new("RateLimit-Reset", ceil_secs(self.reset).to_string()),
+        ]
+    }
+
+    /// The state recorded for `request`, if its budget was checked
+    pub(crate) fn of(request: &Request<'_>) -> Option<Self> {
+        *request.local_cache(|| None::<Self>)
+    }
+}
+
+/// Whole seconds, rounded up
+pub(crate) fn ceil_secs(duration: Duration) -> u64 {
+    duration
+        .as_secs()
+        .saturating_add((duration.subsec_nanos() > 0) as u64)
+}
+
+#[derive(PartialEq, Eq, Hash)]
+enum Key {
+    Identity(String),
+    Token(Digest),
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: SystemTime,
+}
+
+/// The token buckets of an `ApiToken`
+pub(crate) struct RateLimiter {
+    key: [u8; 32],
+    buckets: Mutex<HashMap<Key, Bucket>>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new() -> Self {
+        Self {
+            key: rand::random(),
+            buckets: Mutex::default(),
+        }
+    }
+
+    /// Take one request from the budget of `grant`
+    ///
+    /// The resulting budget state is recorded on `request` for the response headers.
+    pub(crate) fn acquire(
+        &self,
+        request: &Request<'_>,
+        credentials: &Credentials<'_>,
+        grant: &Grant,
+        limit: RateLimit,
+        now: SystemTime,
+    ) -> Result<(), ApiTokenError> {
+        let key = match &grant.identity {
+            Some(identity) => Key::Identity(identity.name().to_string()),
+            None => Key::Token(self.digest(credentials)),
+        };
+        let capacity = limit.requests as f64;
+        let rate = limit.rate();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if buckets.len() > PRUNE_THRESHOLD {
+            buckets.retain(|_, bucket| refill(bucket, now, capacity, rate) < capacity);
+        }
+        let bucket = buckets.entry(key).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+        let tokens = refill(bucket, now, capacity, rate);
+        let allowed = tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+        let state = RateLimitState {
+            limit: limit.requests,
+            remaining: bucket.tokens.floor() as u32,
+            reset: seconds((capacity - bucket.tokens) / rate),
+        };
+        request.local_cache(|| Some(state));
+        match allowed {
+            true => Ok(()),
+            false => Err(ApiTokenError::RateLimited {
+                retry_after: seconds((1.0 - bucket.tokens) / rate),
+            }),
+        }
+    }
+
+    fn digest(&self, credentials: &Credentials<'_>) -> Digest {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts any key size");
+        mac.update(credentials.username().unwrap_or_default().as_bytes());
+        mac.update(b"\0");
+        mac.update(credentials.secret().as_bytes());
+        mac.finalize().into_bytes().into()
+    }
+}
+
+/// Bring `bucket` up to date at `now`, returning its tokens
+fn refill(bucket: &mut Bucket, now: SystemTime, capacity: f64, rate: f64) -> f64 {
+    let elapsed = now.duration_since(bucket.updated).unwrap_or_default();
+    bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * rate).min(capacity);
+    bucket.updated = bucket.updated.max(now);
+    bucket.tokens
+}
+
+fn seconds(secs: f64) -> Duration {
+    Duration::try_from_secs_f64(secs).unwrap_or(Duration::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::figment::providers::{Format, Toml};
+    use rocket::figment::Figment;
+
+    fn parse(toml: &str) -> Option<RateLimit> {
+        Figment::from(Toml::string(toml))
+            .extract_inner("limit")
+            .ok()
+    }
+
+    #[test]
+    fn deserializes_limits() {
+        let limit = parse("limit = { requests = 100, period = 60 }").unwrap();
+        assert_eq!(limit, RateLimit::per_minute(100));
+    }
+
+    #[test]
+    fn rejects_empty_limits() {
+        assert_eq!(parse("limit = { requests = 0, period = 60 }"), None);
+        assert_eq!(parse("limit = { requests = 100, period = 0 }"), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn new_rejects_zero_requests() {
+        RateLimit::new(0, Duration::from_secs(1));
+    }
+
+    #[test]
+    #[should_panic]
+    fn new_rejects_zero_period() {
+        RateLimit::new(1, Duration::ZERO);
+    }
+
+    #[test]
+    fn ceil_secs_saturates() {
+        assert_eq!(ceil_secs(Duration::from_millis(1500)), 2);
+        assert_eq!(ceil_secs(Duration::from_secs(2)), 2);
+        assert_eq!(ceil_secs(Duration::MAX), u64::MAX);
+    }
+}
@@ -0,0 +1,65 @@
+//! Scoped authorization
+
+use crate::{authorize, cannot_authorize, fail, succeed, ApiTokenError};
+use rocket::request::{FromRequest, Outcome};
+use rocket::{Ignite, Request, Rocket, Sentinel};
+use std::marker::PhantomData;
+
+/// A permission that tokens can be granted
+///
+/// ```
+/// use rocket_apitoken::Scope;
+///
+/// struct Write;
+///
+/// impl Scope for Write {
+///     const NAME: &'static str = "write";
+/// }
+/// ```
+pub trait Scope: Send + Sync + 'static {
+    /// The scope name, as given to [`Token::scopes`](crate::Token::scopes)
+    const NAME: &'static str;
+}
+
+/// Request guard that requires a token granted the scope `S`
+///
+/// This guard will succeed if either:
+/// - Authorization is disabled (`enabled = false` in ApiToken)
+/// - A valid bearer token granted `S::NAME` is provided in the Authorization header
+///
+/// # Errors
+/// Fails in the same cases as [`Authorized`](crate::Authorized), and with
+/// [`ApiTokenError::InsufficientScope`] (403 Forbidden) if the token is valid but lacks
+/// the scope.
+pub struct RequireScope<S>(PhantomData<S>);
+
+impl<S> std::fmt::Debug for RequireScope<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("RequireScope")
+            .field(&std::any::type_name::<S>())
+            .finish()
+    }
+}
+
+impl<S: Scope> Sentinel for RequireScope<S> {
+    fn abort(rocket: &Rocket<Ignite>) -> bool {
+        cannot_authorize(rocket)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r, S: Scope> FromRequest<'r> for RequireScope<S> {
+    type Error = ApiTokenError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match authorize(request).await {
+            Ok(Some(grant)) if !grant.scopes.contains(S::NAME) => fail(
+                request,
+                Some(grant),
+                &ApiTokenError::InsufficientScope { scope: S::NAME },
+            ),
+            Ok(grant) => succeed(request, grant.as_deref(), RequireScope(PhantomData)),
+            Err(denial) => fail(request, denial.grant.as_deref(), &denial.error),
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! Where the guard looks for the presented token
+
+use crate::header::{self, Credentials};
+use crate::ApiTokenError;
+use rocket::http::uri::Origin;
+use rocket::serde::Deserialize;
+use rocket::Request;
+use std::borrow::Cow;
+
+/// A place in the request to read the token from
+///
+/// `ApiToken` tries its sources in order and uses the first one present in the
+/// request. In configuration, sources are written as `"authorization"`,
+/// `{ header = "X-API-Key" }`, `{ query = "access_token" }` or
+/// `{ privatecookie = "apitoken" }`.
+///
+/// Query parameters are not used unless configured, since URLs end up in logs and
+/// browser history. When they are, [`ApiTokenFairing`](crate::ApiTokenFairing) must be
+/// attached: it removes the token from the request URI before Rocket logs it, and the
+/// request guards abort launch without it.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum TokenSource {
+    /// A `Bearer` token or `Basic` credentials in the `Authorization` header
+    Authorization,
+    /// The whole value of the named header, e.g. `X-API-Key`
+    Header(String),
+    /// The named query parameter
+    Query(String),
+    /// The named private (encrypted) cookie, as set by
+    /// [`Session::login`](crate::Session::login)
+    #[cfg(feature = "cookies")]
+    PrivateCookie(String),
+}
+
+/// Query tokens removed from the request URI, by parameter name
+#[derive(Default)]
+struct RedactedQuery(Vec<(String, String)>);
+
+impl TokenSource {
+    /// The RFC 6750 `access_token` query parameter
+    pub fn access_token() -> Self {
+        TokenSource::Query("access_token".to_string())
+    }
+
+    /// Read the credentials from `request`
+    ///
+    /// Returns `None` if this source is absent from the request.
+    pub(crate) fn extract<'r>(
+        &self,
+        request: &'r Request<'_>,
+    ) -> Option<Result<Credentials<'r>, ApiTokenError>> {
+        let token = match self {
+            TokenSource::Authorization => {
+                return request
+                    .headers()
+                    .get_one("Authorization")
+                    .map(header::parse_authorization)
+            }
+            TokenSource::Header(name) => request
+                .headers()
+                .get_one(name)
+                .map(|value| Ok(Cow::Borrowed(value.trim()))),
+            TokenSource::Query(name) => {
+                let redacted = &request.local_cache(RedactedQuery::default).0;
+                match redacted.iter().find(|(field, _)| field == name) {
+                    Some((_, value)) => Some(Ok(Cow::Borrowed(value.as_str()))),
+                    None => request.query_value::<&str>(name).map(|value| {
+                        value
+                            .map(Cow::Borrowed)
+                            .map_err(|_| ApiTokenError::MalformedScheme)
+                    }),
+                }
+            }
+            #[cfg(feature = "cookies")]
+            TokenSource::PrivateCookie(name) => request
+                .cookies()
+                .get_private(name)
+                .map(|cookie| Ok(Cow::Owned(cookie.value().to_string()))),
+        };
+        token.map(|token| token.map(Credentials::Token))
+    }
+}
+
+/// Move the tokens of query `sources` out of the request URI
+///
+/// Run before Rocket logs the request, so the tokens never reach the logs. The
+/// removed values remain available to [`TokenSource::extract`].
+pub(crate) fn redact_query(request: &mut Request<'_>, sources: &[TokenSource]) {
+    let names: Vec<&str> = sources
+        .iter()
+        .filter_map(|source| match source {
+            TokenSource::Query(name) => Some(name.as_str()),
+            _ => None,
+        })
+        .collect();
+    let Some(query) = request.uri().query() else {
+        return;
+    };
+    let mut redacted = Vec::new();
+    let mut kept = Vec::new();
+    for segment in query.raw_segments() {
+        let (name, value) = segment.split_at_byte(b'=');
+        let name = name.url_decode_lossy();
+        if names.contains(&name.as_ref()) {
+            redacted.push((name.into_owned(), value.url_decode_lossy().into_owned()));
+        } else {
+            kept.push(segment.as_str());
+        }
+    }
+    if redacted.is_empty() {
+        return;
+    }
+    let uri = match kept.is_empty() {
+        true => request.uri().path().as_str().to_string(),
+        false => format!("{}?{}", request.uri().path(), kept.join("&")),
+    };
+    if let Ok(uri) = Origin::parse_owned(uri) {
+        request.set_uri(uri);
+        request.local_cache(|| RedactedQuery(redacted));
+    }
+}
@@ -0,0 +1,229 @@
+//! Tokens stored in a SQL table
+
+use crate::{hash, ApiTokenError, Credentials, Grant, Identity, TokenValidator};
+use sqlx::{Database, Pool};
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// The schema read by [`SqlTokenStore`]
+///
+/// Also shipped as `migrations/20240601000000_api_tokens.sql` for use with `sqlx migrate`.
+pub const SQL_MIGRATION: &str = include_str!("../migrations/20240601000000_api_tokens.sql");
+
+const LOOKUP: &str = "SELECT name, client_id, scopes, not_before, expires_at, revoked \
+                      FROM api_tokens WHERE token_hash = $1";
+
+/// A row of the `api_tokens` table
+type Row = (
+    Option<String>,
+    Option<String>,
+    String,
+    Option<i64>,
+    Option<i64>,
+    bool,
+);
+
+/// A [`TokenValidator`] looking bearer tokens up in the `api_tokens` table
+///
+/// Available for SQLite with the `sqlite` feature and for PostgreSQL with the
+/// `postgres` feature. Tokens are stored as hex encoded SHA-256 digests (see
+/// [`token_hash`](Self::token_hash)), together with their identity, space separated
+/// scopes, validity window as Unix timestamps, and a revoked flag; see
+/// [`SQL_MIGRATION`] for the schema. Database errors fail the request with
+/// [`ApiTokenError::Unavailable`].
+///
+/// The store takes a `sqlx` pool. `rocket_db_pools` 0.2 uses the same `sqlx` version,
+/// so the pool of a `rocket_db_pools` database can be passed in directly, e.g. from an
+/// ignite fairing attached after the database:
+///
+/// ```no_run
+/// # use rocket::fairing::AdHoc;
+/// # use rocket_apitoken::{ApiToken, SqlTokenStore};
+/// # async fn connect() -> sqlx::SqlitePool { unimplemented!() }
+/// # let rocket = rocket::build();
+/// rocket.attach(AdHoc::on_ignite("API tokens", |rocket| async {
+///     // With rocket_db_pools: `let pool = Db::fetch(&rocket).unwrap().0.clone();`
+///     let pool = connect().await;
+///     let store = SqlTokenStore::new(pool);
+///     store.migrate().await.expect("api_tokens table");
+///     rocket.manage(ApiToken::new(vec![], true).with_validator(store))
+/// }));
+/// ```
+pub struct SqlTokenStore<DB: Database> {
+    pool: Pool<DB>,
+}
+
+impl<DB: Database> SqlTokenStore<DB> {
+    /// Look tokens up through `pool`
+    pub fn new(pool: Pool<DB>) -> Self {
+        Self { pool }
+    }
+
+    /// The value of the `token_hash` column for `token`
+    pub fn token_hash(token: &str) -> String {
+        hash::sha256(token)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+impl<DB: Database> fmt::Debug for SqlTokenStore<DB> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SqlTokenStore").finish_non_exhaustive()
+    }
+}
+
+fn grant(
+    (name, client_id, scopes, not_before, expires_at, revoked): Row,
+) -> Result<Grant, ApiTokenError> {
+    if revoked {
+        return Err(ApiTokenError::Revoked);
+    }
+    let time = |secs: i64| SystemTime::UNIX_EPOCH + Duration::from_secs(secs.max(0) as u64);
+    let mut grant = Grant::new().scopes(scopes.split_whitespace());
+    if let Some(name) = name {
+        let identity = Identity::new(name);
+        grant = grant.identity(match client_id {
+            Some(client_id) => identity.with_client_id(client_id),
+            None => identity,
+        });
+    }
+    if let Some(secs) = not_before {
+        grant = grant.not_before(time(secs));
+    }
+    if let Some(secs) = expires_at {
+        grant = grant.expires_at(time(secs));
+    }
+    Ok(grant)
+}
+
+macro_rules! sql_token_store {
+    ($db:ty) => {
+        impl SqlTokenStore<$db> {
+            /// Create the `api_tokens` table if it does not exist
+            pub async fn migrate(&self) -> Result<(), sqlx::Error> {
+                sqlx::query(SQL_MIGRATION).execute(&self.pool).await?;
+                Ok(())
+            }
+        }
+
+        #[rocket::async_trait]
+        impl TokenValidator for SqlTokenStore<$db> {
+            async fn validate(
+                &self,
+                credentials: &Credentials<'_>,
+            ) -> Result<Arc<Grant>, ApiTokenError> {
+                let Credentials::Token(token) = credentials else {
+                    return Err(ApiTokenError::UnknownToken);
+                };
+                let row = sqlx::query_as::<_, Row>(LOOKUP)
+                    .bind(Self::token_hash(token))
+                    .fetch_optional(&self.pool)
+                    .await
+                    .map_err(|error| {
+                        rocket::error!("API token lookup failed: {}", error);
+                        ApiTokenError::Unavailable
+                    })?;
+                match row {
+                    Some(row) => grant(row).map(Arc::new),
+                    None => Err(ApiTokenError::UnknownToken),
+                }
+            }
+        }
+    };
+}
+
+#[cfg(feature = "sqlite")]
+sql_token_store!(sqlx::Sqlite);
+#[cfg(feature = "postgres")]
+sql_token_store!(sqlx::Postgres);
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use sqlx::sqlite::SqlitePoolOptions;
+    use sqlx::Sqlite;
+
+    /// A store over a fresh in-memory database holding a few tokens
+    async fn store() -> SqlTokenStore<Sqlite> {
+        // Each connection to `sqlite::memory:` opens its own database.
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        let store = SqlTokenStore::new(pool);
+        store.migrate().await.unwrap();
+        let rows = [
+            ("valid", Some(4_000_000_000i64), false),
+            ("expired", Some(1_000), false),
+            ("revoked", None, true),
+        ];
+        for (token, expires_at, revoked) in rows {
+            sqlx::query(
+                "INSERT INTO api_tokens (token_hash, name, client_id, scopes, expires_at, revoked) \
+                 VALUES ($1, 'alice', 'cli', 'read write', $2, $3)",
+            )
+            .bind(SqlTokenStore::<Sqlite>::token_hash(token))
+            .bind(expires_at)
+            .bind(revoked)
+            .execute(&store.pool)
+            .await
+            .unwrap();
+        }
+        store
+    }
+
+    fn token(token: &str) -> Credentials<'_> {
+        Credentials::Token(token.into())
+    }
+
+    #[rocket::async_test]
+    async fn accepts_valid_row() {
+        let grant = store().await.validate(&token("valid")).await.unwrap();
+        let identity = grant.identity.as_ref().unwrap();
+        assert_eq!(identity.name(), "alice");
+        assert_eq!(identity.client_id(), Some("cli"));
+        assert!(grant.scopes.contains("read") && grant.scopes.contains("write"));
+        assert!(grant.check_validity(SystemTime::now()).is_ok());
+    }
+
+    #[rocket::async_test]
+    async fn reports_expired_row() {
+        let grant = store().await.validate(&token("expired")).await.unwrap();
+        let result = grant.check_validity(SystemTime::now());
+        assert!(matches!(result, Err(ApiTokenError::Expired)));
+    }
+
+    #[rocket::async_test]
+    async fn rejects_revoked_row() {
+        let result = store().await.validate(&token("revoked")).await;
+        assert!(matches!(result, Err(ApiTokenError::Revoked)));
+    }
+
+    #[rocket::async_test]
+    async fn rejects_unknown_hash() {
+        let result = store().await.validate(&token("unknown")).await;
+        assert!(matches!(result, Err(ApiTokenError::UnknownToken)));
+    }
+
+    #[rocket::async_test]
+    async fn passes_on_basic_credentials() {
+        let credentials = Credentials::Basic {
+            username: "alice".to_string(),
+            password: "valid".to_string(),
+        };
+        let result = store().await.validate(&credentials).await;
+        assert!(matches!(result, Err(ApiTokenError::UnknownToken)));
+    }
+
+    #[rocket::async_test]
+    async fn reports_database_errors_as_unavailable() {
+        let store = store().await;
+        store.pool.close().await;
+        let result = store.validate(&token("valid")).await;
+        assert!(matches!(result, Err(ApiTokenError::Unavailable)));
+    }
+}
@@ -0,0 +1,452 @@
+//! The in-memory token set behind `ApiToken`
+
+use crate::hash::{self, Presented, Secret};
+use crate::header::Credentials;
+use crate::token::{Grant, TokenSecret};
+use crate::{ApiTokenError, ConfigError, Identity, Token, TokenValidator};
+use arc_swap::ArcSwap;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::sync::Arc;
+use subtle::{Choice, ConditionallySelectable};
+
+/// A hot-swappable set of token secrets and what they grant
+///
+/// Shared between `ApiToken` and background tasks that update it.
+pub(crate) struct TokenStore {
+    key: [u8; 32],
+    entries: ArcSwap<Vec<Entry>>,
+}
+
+#[derive(Clone)]
+pub(crate) struct Entry {
+    pub(crate) secret: Secret,
+    /// Set for HTTP Basic credentials, which match only under this username
+    pub(crate) username: Option<String>,
+    pub(crate) grant: Arc<Grant>,
+    /// Revoked entries are kept so that their use can be reported as such
+    pub(crate) revoked: bool,
+}
+
+impl TokenStore {
+    pub(crate) fn new() -> Self {
+        Self {
+            key: rand::random(),
+            entries: ArcSwap::default(),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of tokens that are not revoked
+    pub(crate) fn len(&self) -> usize {
+        self.entries
+            .load()
+            .iter()
+            .filter(|entry| !entry.revoked)
+            .count()
+    }
+
+    /// Whether any token that is not revoked is a set of HTTP Basic credentials
+    pub(crate) fn has_basic(&self) -> bool {
+        self.entries
+            .load()
+            .iter()
+            .any(|entry| !entry.revoked && entry.username.is_some())
+    }
+
+    pub(crate) fn add(&self, token: Token) {
+        let entry = self.entry(token);
+        self.entries.rcu(|entries| {
+            let mut entries = Vec::clone(entries);
+            insert(&mut entries, entry.clone());
+            entries
+        });
+    }
+
+    pub(crate) fn revoke(&self, token: &str) -> bool {
+        let presented = self.presented(token);
+        self.revoke_matching(|entry| {
+            entry.username.is_none() && entry.secret.matches(&presented).into()
+        })
+    }
+
+    pub(crate) fn revoke_user(&self, username: &str) -> bool {
+        self.revoke_matching(|entry| entry.username.as_deref() == Some(username))
+    }
+
+    pub(crate) fn revoke_secret(&self, secret: &Secret) -> bool {
+        self.revoke_matching(|entry| entry.secret == *secret)
+    }
+
+    fn revoke_matching(&self, matches: impl Fn(&Entry) -> bool) -> bool {
+        let matches = |entry: &Entry| !entry.revoked && matches(entry);
+        let previous = self.entries.rcu(|entries| {
+            entries
+                .iter()
+                .map(|entry| Entry {
+                    revoked: entry.revoked || matches(entry),
+                    ..entry.clone()
+                })
+                .collect::<Vec<_>>()
+        });
+        previous.iter().any(matches)
+    }
+
+    pub(crate) fn replace_all(&self, tokens: impl IntoIterator<Item = Token>) {
+        let mut entries = Vec::new();
+        for token in tokens {
+            insert(&mut entries, self.entry(token));
+        }
+        self.store(entries);
+    }
+
+    /// Build the entries of `tokens`, along with the empty and duplicate tokens among them
+    pub(crate) fn prepare(
+        &self,
+        tokens: impl IntoIterator<Item = Token>,
+    ) -> (Vec<Entry>, Vec<ConfigError>) {
+        let mut issues = Vec::new();
+        let mut entries = Vec::new();
+        for (index, token) in tokens.into_iter().enumerate() {
+            let entry = self.entry(token);
+            if self.is_empty_token(&entry.secret) {
+                issues.push(ConfigError::EmptyToken { index });
+            }
+            if entry.secret.is_slow() && entry.username.is_none() {
+                issues.push(ConfigError::SlowHashWithoutUsername { index });
+            }
+            if insert(&mut entries, entry) {
+                issues.push(ConfigError::DuplicateToken { index });
+            }
+        }
+        (entries, issues)
+    }
+
+    /// Replace all tokens with `tokens`, unless they are empty or have any issues
+    ///
+    /// Guards against wiping out every credential with a blank or truncated source.
+    pub(crate) fn replace_checked(
+        &self,
+        tokens: impl IntoIterator<Item = Token>,
+    ) -> Result<(), ConfigError> {
+        let (entries, issues) = self.prepare(tokens);
+        if let Some(issue) = issues.into_iter().next() {
+            return Err(issue);
+        }
+        if entries.is_empty() {
+            return Err(ConfigError::NoTokens);
+        }
+        self.store(entries);
+        Ok(())
+    }
+
+    pub(crate) fn store(&self, entries: Vec<Entry>) {
+        self.entries.store(Arc::new(entries));
+    }
+
+    pub(crate) fn entry(&self, token: Token) -> Entry {
+        let secret = match token.secret {
+            TokenSecret::Plain(plain) => Secret::Keyed(self.digest(&plain)),
+            TokenSecret::Hashed(secret) => secret,
+        };
+        let mut grant = token.grant;
+        if let (None, Some(username)) = (&grant.identity, &token.username) {
+            grant.identity = Some(Identity::new(username.as_str()));
+        }
+        Entry {
+            secret,
+            username: token.username,
+            grant: Arc::new(grant),
+            revoked: false,
+        }
+    }
+
+    pub(crate) fn is_empty_token(&self, secret: &Secret) -> bool {
+        *secret == Secret::Keyed(self.digest("")) || *secret == Secret::Sha256(hash::sha256(""))
+    }
+
+    fn presented(&self, token: &str) -> Presented {
+        Presented {
+            keyed: self.digest(token),
+            sha256: hash::sha256(token),
+        }
+    }
+
+    /// Find the grant of the entry matching `username` and `token` by its digest.
+    ///
+    /// Bare tokens (`username` of `None`) only match entries without a username.
+    /// Every stored digest is checked, so the time taken does not depend on which
+    /// token (if any) matched. Slow password hashes are left to [`Self::find_slow`].
+    pub(crate) fn find(
+        &self,
+        username: Option<&str>,
+        token: &str,
+    ) -> Result<Arc<Grant>, ApiTokenError> {
+        let presented = self.presented(token);
+        let entries = self.entries.load();
+        let mut found = Choice::from(0);
+        let mut found_revoked = Choice::from(0);
+        let mut index = 0u64;
+        for (i, entry) in entries.iter().enumerate() {
+            let user = Choice::from((entry.username.as_deref() == username) as u8);
+            let matches = entry.secret.matches(&presented) & user;
+            let revoked = Choice::from(entry.revoked as u8);
+            index.conditional_assign(&(i as u64), matches & !revoked);
+            found |= matches & !revoked;
+            found_revoked |= matches & revoked;
+        }
+        if bool::from(found) {
+            Ok(entries[index as usize].grant.clone())
+        } else if bool::from(found_revoked) {
+            Err(ApiTokenError::Revoked)
+        } else {
+            Err(ApiTokenError::UnknownToken)
+        }
+    }
+
+    /// Find the grant of the slow password hash of `username` that `password` matches
+    ///
+    /// Only the hashes registered for `username` are verified, on the blocking thread
+    /// pool, so a request costs at most as many slow hashes as one user has.
+    #[cfg(any(feature = "argon2", feature = "bcrypt"))]
+    pub(crate) async fn find_slow(
+        &self,
+        username: &str,
+        password: &str,
+    ) -> Result<Arc<Grant>, ApiTokenError> {
+        let candidates: Vec<Entry> = self
+            .entries
+            .load()
+            .iter()
+            .filter(|entry| entry.secret.is_slow() && entry.username.as_deref() == Some(username))
+            .cloned()
+            .collect();
+        if candidates.is_empty() {
+            return Err(ApiTokenError::UnknownToken);
+        }
+        let password = password.to_string();
+        let matched = rocket::tokio::task::spawn_blocking(move || {
+            candidates
+                .into_iter()
+                .filter(|entry| entry.secret.verify(&password))
+                .min_by_key(|entry| entry.revoked)
+        })
+        .await
+        .map_err(|_| ApiTokenError::Unavailable)?;
+        match matched {
+            Some(entry) if !entry.revoked => Ok(entry.grant),
+            Some(_) => Err(ApiTokenError::Revoked),
+            None => Err(ApiTokenError::UnknownToken),
+        }
+    }
+
+    fn digest(&self, value: &str) -> hash::Digest {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts any key size");
+        mac.update(value.as_bytes());
+        mac.finalize().into_bytes().into()
+    }
+}
+
+#[rocket::async_trait]
+impl TokenValidator for TokenStore {
+    async fn validate(&self, credentials: &Credentials<'_>) -> Result<Arc<Grant>, ApiTokenError> {
+        let found = self.find(credentials.username(), credentials.secret());
+        #[cfg(any(feature = "argon2", feature = "bcrypt"))]
+        if let (Err(ApiTokenError::UnknownToken), Some(username)) = (&found, credentials.username())
+        {
+            return self.find_slow(username, credentials.secret()).await;
+        }
+        found
+    }
+}
+
+/// Insert `entry`, returning whether it replaced an existing one
+pub(crate) fn insert(entries: &mut Vec<Entry>, entry: Entry) -> bool {
+    match entries
+        .iter_mut()
+        .find(|e| e.secret == entry.secret && e.username == entry.username)
+    {
+        Some(existing) => {
+            *existing = entry;
+            true
+        }
+        None => {
+            entries.push(entry);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TokenHash;
+
+    fn store(tokens: impl IntoIterator<Item = Token>) -> TokenStore {
+        let store = TokenStore::new();
+        store.replace_all(tokens);
+        store
+    }
+
+    fn name(grant: &Grant) -> Option<&str> {
+        grant.identity.as_ref().map(Identity::name)
+    }
+
+    #[test]
+    fn finds_valid_tokens() {
+        let store = store([
+            Token::bearer("first").identity(Identity::new("one")),
+            Token::bearer("second").identity(Identity::new("two")),
+        ]);
+        assert_eq!(name(&store.find(None, "first").unwrap()), Some("one"));
+        assert_eq!(name(&store.find(None, "second").unwrap()), Some("two"));
+    }
+
+    #[test]
+    fn rejects_invalid_tokens() {
+        let store = store([Token::bearer("secret-token")]);
+        for token in [
+            "",
+            "secret",
+            "secret-token ",
+            "SECRET-TOKEN",
+            "secret-token2",
+        ] {
+            let result = store.find(None, token);
+            assert!(
+                matches!(result, Err(ApiTokenError::UnknownToken)),
+                "{token:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn finds_hashed_tokens() {
+        let hex: String = hash::sha256("secret-token")
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        let hash: TokenHash = format!("sha256:{}", hex).parse().unwrap();
+        let store = store([Token::hashed(hash)]);
+        assert!(store.find(None, "secret-token").is_ok());
+        assert!(matches!(
+            store.find(None, "other"),
+            Err(ApiTokenError::UnknownToken)
+        ));
+    }
+
+    #[test]
+    fn matches_basic_credentials_by_username() {
+        let store = store([Token::basic("prometheus", "s3cret")]);
+        let grant = store.find(Some("prometheus"), "s3cret").unwrap();
+        assert_eq!(name(&grant), Some("prometheus"));
+        assert!(matches!(
+            store.find(Some("grafana"), "s3cret"),
+            Err(ApiTokenError::UnknownToken)
+        ));
+        assert!(matches!(
+            store.find(Some("prometheus"), "wrong"),
+            Err(ApiTokenError::UnknownToken)
+        ));
+    }
+
+    #[test]
+    fn keeps_basic_and_bearer_tokens_apart() {
+        let store = store([Token::basic("prometheus", "s3cret"), Token::bearer("token")]);
+        assert!(store.find(None, "s3cret").is_err());
+        assert!(store.find(Some("prometheus"), "token").is_err());
+        assert!(store.find(None, "token").is_ok());
+    }
+
+    #[test]
+    fn reports_revoked_tokens() {
+        let store = store([Token::bearer("first"), Token::bearer("second")]);
+        assert!(store.revoke("first"));
+        assert!(!store.revoke("first"));
+        assert!(!store.revoke("unknown"));
+        assert!(matches!(
+            store.find(None, "first"),
+            Err(ApiTokenError::Revoked)
+        ));
+        assert!(store.find(None, "second").is_ok());
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn re_adding_a_revoked_token_restores_it() {
+        let store = store([Token::bearer("first")]);
+        store.revoke("first");
+        store.add(Token::bearer("first").identity(Identity::new("again")));
+        assert_eq!(name(&store.find(None, "first").unwrap()), Some("again"));
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn revokes_basic_users() {
+        let store = store([
+            Token::basic("prometheus", "s3cret"),
+            Token::bearer("s3cret"),
+        ]);
+        assert!(store.revoke("s3cret"));
+        assert!(store.find(Some("prometheus"), "s3cret").is_ok());
+        assert!(store.revoke_user("prometheus"));
+        assert!(matches!(
+            store.find(Some("prometheus"), "s3cret"),
+            Err(ApiTokenError::Revoked)
+        ));
+    }
+
+    #[test]
+    fn replaces_all_tokens() {
+        let store = store([Token::bearer("old")]);
+        store.revoke("old");
+        store.replace_all([Token::bearer("new")]);
+        assert!(matches!(
+            store.find(None, "old"),
+            Err(ApiTokenError::UnknownToken)
+        ));
+        assert!(store.find(None, "new").is_ok());
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn rejects_slow_hashes_without_username() {
+        #[cfg(feature = "bcrypt")]
+        {
+            let hash = TokenHash::bcrypt(&bcrypt::hash("s3cret", 4).unwrap()).unwrap();
+            let (_, issues) = TokenStore::new().prepare([
+                Token::basic_hashed("prometheus", hash.clone()),
+                Token::hashed(hash),
+            ]);
+            assert!(matches!(
+                issues[..],
+                [ConfigError::SlowHashWithoutUsername { index: 1 }]
+            ));
+        }
+        let (_, issues) = TokenStore::new().prepare([Token::hashed(TokenHash::sha256_of("t"))]);
+        assert!(issues.is_empty());
+    }
+
+    #[cfg(feature = "bcrypt")]
+    #[rocket::async_test]
+    async fn verifies_slow_hashes_of_the_presented_username_only() {
+        let hash = TokenHash::bcrypt(&bcrypt::hash("s3cret", 4).unwrap()).unwrap();
+        let store = store([
+            Token::basic_hashed("prometheus", hash.clone()),
+            Token::basic_hashed("grafana", TokenHash::sha256_of("other")),
+        ]);
+        let grant = store.find_slow("prometheus", "s3cret").await.unwrap();
+        assert_eq!(name(&grant), Some("prometheus"));
+        assert!(store.find_slow("grafana", "s3cret").await.is_err());
+        assert!(store.find_slow("prometheus", "wrong").await.is_err());
+        assert!(store.find(None, "s3cret").is_err());
+        store.revoke_user("prometheus");
+        assert!(matches!(
+            store.find_slow("prometheus", "s3cret").await,
+            Err(ApiTokenError::Revoked)
+        ));
+    }
+}
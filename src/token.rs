@@ -0,0 +1,223 @@
+//! Tokens and the identities they carry
+
+use crate::hash::Secret;
+use crate::{ApiTokenError, RateLimit, TokenHash};
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::SystemTime;
+
+/// The client a token belongs to
+///
+/// Attach an identity to a [`Token`] when registering it with `ApiToken`; handlers can
+/// then read it back through the [`AuthorizedAs`](crate::AuthorizedAs) guard.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Identity {
+    name: String,
+    client_id: Option<String>,
+    metadata: BTreeMap<String, String>,
+}
+
+impl Identity {
+    /// Create a new identity with the given name
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Set the client id
+    pub fn with_client_id(mut self, client_id: impl Into<String>) -> Self {
+        self.client_id = Some(client_id.into());
+        self
+    }
+
+    /// Add a metadata entry
+    pub fn with_metadata(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.metadata.insert(key.into(), value.into());
+        self
+    }
+
+    /// The name of this identity
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The client id, if one was set
+    pub fn client_id(&self) -> Option<&str> {
+        self.client_id.as_deref()
+    }
+
+    /// Look up a metadata entry
+    pub fn metadata(&self, key: &str) -> Option<&str> {
+        self.metadata.get(key).map(String::as_str)
+    }
+
+    /// All metadata entries
+    pub fn all_metadata(&self) -> &BTreeMap<String, String> {
+        &self.metadata
+    }
+}
+
+pub(crate) enum TokenSecret {
+    Plain(String),
+    Hashed(Secret),
+}
+
+/// A token to register with `ApiToken`, together with its identity and scopes
+///
+/// ```
+/// use rocket_apitoken::{Identity, Token};
+///
+/// let token = Token::bearer("secret-token")
+///     .identity(Identity::new("dashboard"))
+///     .scopes(["read"]);
+/// ```
+pub struct Token {
+    pub(crate) secret: TokenSecret,
+    pub(crate) username: Option<String>,
+    pub(crate) grant: Grant,
+}
+
+impl Token {
+    /// A token given in plaintext
+    ///
+    /// The plaintext is discarded once the token is added to an `ApiToken`.
+    pub fn bearer(token: impl Into<String>) -> Self {
+        Self::from_secret(TokenSecret::Plain(token.into()))
+    }
+
+    /// A pre-hashed token
+    pub fn hashed(hash: TokenHash) -> Self {
+        Self::from_secret(TokenSecret::Hashed(hash.0))
+    }
+
+    /// HTTP Basic credentials given in plaintext
+    ///
+    /// Requests must send `Authorization: Basic base64(username:password)`. Unless an
+    /// identity is attached, the token is identified by `username`.
+    pub fn basic(username: impl Into<String>, password: impl Into<String>) -> Self {
+        Self::bearer(password).username(username)
+    }
+
+    /// HTTP Basic credentials with a pre-hashed password
+    pub fn basic_hashed(username: impl Into<String>, hash: TokenHash) -> Self {
+        Self::hashed(hash).username(username)
+    }
+
+    fn from_secret(secret: TokenSecret) -> Self {
+        Self {
+            secret,
+            username: None,
+            grant: Grant::new(),
+        }
+    }
+
+    fn username(mut self, username: impl Into<String>) -> Self {
+        self.username = Some(username.into());
+        self
+    }
+
+    /// Attach an identity to the token
+    pub fn identity(mut self, identity: Identity) -> Self {
+        self.grant = self.grant.identity(identity);
+        self
+    }
+
+    /// Grant scopes to the token
+    ///
+    /// Scopes are checked by the [`RequireScope`](crate::RequireScope) guard.
+    pub fn scopes<S: Into<String>>(mut self, scopes: impl IntoIterator<Item = S>) -> Self {
+        self.grant = self.grant.scopes(scopes);
+        self
+    }
+
+    /// Reject the token before `time`
+    pub fn not_before(mut self, time: SystemTime) -> Self {
+        self.grant = self.grant.not_before(time);
+        self
+    }
+
+    /// Reject the token from `time` on
+    pub fn expires_at(mut self, time: SystemTime) -> Self {
+        self.grant = self.grant.expires_at(time);
+        self
+    }
+
+    /// Limit the requests made with the token, instead of the default budget
+    pub fn rate_limit(mut self, limit: RateLimit) -> Self {
+        self.grant = self.grant.rate_limit(limit);
+        self
+    }
+}
+
+/// What valid credentials grant to a request
+///
+/// Returned by [`TokenValidator`](crate::TokenValidator) implementations; the built-in
+/// validators derive it from the registered [`Token`] or from JWT claims.
+///
+/// ```
+/// use rocket_apitoken::{Grant, Identity};
+///
+/// let grant = Grant::new()
+///     .identity(Identity::new("ci"))
+///     .scopes(["deploy"]);
+/// ```
+#[derive(Debug, Default)]
+pub struct Grant {
+    pub(crate) identity: Option<Identity>,
+    pub(crate) scopes: BTreeSet<String>,
+    pub(crate) not_before: Option<SystemTime>,
+    pub(crate) expires_at: Option<SystemTime>,
+    pub(crate) rate_limit: Option<RateLimit>,
+    /// The claims of a JWT, when the grant came from one
+    #[cfg(feature = "jwt")]
+    pub(crate) claims: Option<crate::Claims>,
+}
+
+impl Grant {
+    /// A grant without identity or scopes
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Attach an identity
+    pub fn identity(mut self, identity: Identity) -> Self {
+        self.identity = Some(identity);
+        self
+    }
+
+    /// Grant scopes
+    pub fn scopes<S: Into<String>>(mut self, scopes: impl IntoIterator<Item = S>) -> Self {
+        self.scopes.extend(scopes.into_iter().map(Into::into));
+        self
+    }
+
+    /// Reject the request before `time`
+    pub fn not_before(mut self, time: SystemTime) -> Self {
+        self.not_before = Some(time);
+        self
+    }
+
+    /// Reject the request from `time` on
+    pub fn expires_at(mut self, time: SystemTime) -> Self {
+        self.expires_at = Some(time);
+        self
+    }
+
+    /// Limit the requests made with these credentials, instead of the default budget
+    pub fn rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limit = Some(limit);
+        self
+    }
+
+    /// Check that `now` lies within the validity window of the token
+    pub(crate) fn check_validity(&self, now: SystemTime) -> Result<(), ApiTokenError> {
+        if self.not_before.is_some_and(|not_before| now < not_before) {
+            return Err(ApiTokenError::NotYetValid);
+        }
+        if self.expires_at.is_some_and(|expires_at| now >= expires_at) {
+            return Err(ApiTokenError::Expired);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,186 @@
+//! `tracing` spans and events of authorization decisions
+
+use crate::{header, ApiTokenError, AuthOutcome, AuthResult, Identity};
+use rocket::Request;
+use tracing::field::Empty;
+use tracing::Span;
+
+/// A span around the validation of the credentials of `request`
+///
+/// Only the scheme of the `Authorization` header is recorded, never its credentials.
+pub(crate) fn span(request: &Request<'_>) -> Span {
+    let authorization =
+        request
+            .headers()
+            .get_one("Authorization")
+            .map(|value| match header::split(value) {
+                Some((scheme, _)) => format!("{} <redacted>", scheme),
+                None => "<redacted>".to_string(),
+            });
+    tracing::info_span!(
+        "apitoken.authorize",
+        method = %request.method(),
+        route = request.route().map(|route| route.uri.as_str()),
+        authorization,
+        outcome = Empty,
+        identity = Empty,
+        reason = Empty,
+    )
+}
+
+/// Record the result of validation on `span`
+pub(crate) fn record(span: &Span, result: &AuthResult) {
+    match result {
+        Ok(Some(grant)) => {
+            span.record("outcome", AuthOutcome::Allowed.as_str());
+            if let Some(identity) = &grant.identity {
+                span.record("identity", identity.name());
+            }
+        }
+        Ok(None) => {
+            span.record("outcome", AuthOutcome::Bypassed.as_str());
+        }
+        Err(denial) => {
+            span.record("outcome", AuthOutcome::Denied.as_str());
+            if let Some(identity) = denial
+                .grant
+                .as_ref()
+                .and_then(|grant| grant.identity.as_ref())
+            {
+                span.record("identity", identity.name());
+            }
+            span.record("reason", tracing::field::display(&denial.error));
+        }
+    }
+}
+
+/// Emit an event for the decision of a guard
+pub(crate) fn decision(
+    outcome: AuthOutcome,
+    reason: Option<&ApiTokenError>,
+    identity: Option<&Identity>,
+) {
+    let identity = identity.map(Identity::name);
+    match reason {
+        Some(reason) => tracing::info!(
+            outcome = outcome.as_str(),
+            identity,
+            reason = %reason,
+            "request denied"
+        ),
+        None => tracing::debug!(outcome = outcome.as_str(), identity, "request authorized"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ApiToken, Authorized, Token};
+    use rocket::http::Header;
+    use rocket::local::blocking::Client;
+    use std::fmt;
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    /// Collects the fields of every span and event as `name=value`
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl Visit for Recorder {
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("{}={:?}", field.name(), value));
+        }
+
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("{}={}", field.name(), value));
+        }
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            span.record(&mut self.clone());
+            Id::from_u64(1)
+        }
+
+        fn record(&self, _: &Id, values: &Record<'_>) {
+            values.record(&mut self.clone());
+        }
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            event.record(&mut self.clone());
+        }
+
+        fn enter(&self, _: &Id) {}
+
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[rocket::get("/")]
+    fn index(_authorized: Authorized) {}
+
+    fn fields(authorization: &str) -> Vec<String> {
+        let api_token = ApiToken::from_tokens(
+            [
+                Token::bearer("secret-token"),
+                Token::basic("prometheus", "s3cret"),
+            ],
+            true,
+        );
+        let rocket = rocket::build()
+            .manage(api_token)
+            .mount("/", rocket::routes![index]);
+        let client = Client::untracked(rocket).unwrap();
+        let recorder = Recorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            client
+                .get("/")
+                .header(Header::new("Authorization", authorization.to_string()))
+                .dispatch();
+        });
+        let fields = recorder.0.lock().unwrap().clone();
+        fields
+    }
+
+    #[test]
+    fn records_only_the_scheme_of_the_credentials() {
+        for (authorization, secret) in [
+            ("Bearer secret-token", "secret-token"),
+            ("Bearer guess-token", "guess-token"),
+            // prometheus:s3cret
+            ("Basic cHJvbWV0aGV1czpzM2NyZXQ=", "cHJvbWV0aGV1czpzM2NyZXQ="),
+        ] {
+            let fields = fields(authorization);
+            let scheme = authorization.split(' ').next().unwrap();
+            let expected = format!("authorization={} <redacted>", scheme);
+            assert!(fields.contains(&expected), "{fields:?}");
+            assert!(fields.iter().any(|field| field.starts_with("outcome=")));
+            for field in &fields {
+                assert!(!field.contains(secret), "{field}");
+                assert!(!field.contains("s3cret"), "{field}");
+            }
+        }
+    }
+
+    #[test]
+    fn redacts_credentials_without_a_scheme() {
+        let fields = fields("secret-token");
+        assert!(
+            fields.contains(&"authorization=<redacted>".to_string()),
+            "{fields:?}"
+        );
+        assert!(fields.iter().all(|field| !field.contains("secret-token")));
+    }
+}
@@ -0,0 +1,64 @@
+//! Pluggable backends deciding which credentials are valid
+
+use crate::header::Credentials;
+use crate::token::Grant;
+use crate::ApiTokenError;
+use std::sync::Arc;
+
+/// A backend that checks presented credentials
+///
+/// `ApiToken` parses the credentials from the request, then asks its built-in token
+/// set and each validator added with [`ApiToken::with_validator`](crate::ApiToken::with_validator)
+/// in turn, until one of them returns anything but [`ApiTokenError::UnknownToken`].
+/// The validity window of the returned [`Grant`] is checked afterwards against the
+/// clock of the `ApiToken`.
+///
+/// ```
+/// use rocket_apitoken::{ApiTokenError, Credentials, Grant, Identity, TokenValidator};
+/// use std::sync::Arc;
+///
+/// struct Staging;
+///
+/// #[rocket::async_trait]
+/// impl TokenValidator for Staging {
+///     async fn validate(&self, credentials: &Credentials<'_>) -> Result<Arc<Grant>, ApiTokenError> {
+///         match credentials.secret().strip_prefix("staging-") {
+///             Some(name) => Ok(Arc::new(Grant::new().identity(Identity::new(name)))),
+///             None => Err(ApiTokenError::UnknownToken),
+///         }
+///     }
+/// }
+/// ```
+#[rocket::async_trait]
+pub trait TokenValidator: Send + Sync + 'static {
+    /// Check `credentials` and return what they grant
+    ///
+    /// Return [`ApiTokenError::UnknownToken`] for credentials this validator does not
+    /// know, so that the next one is asked.
+    async fn validate(&self, credentials: &Credentials<'_>) -> Result<Arc<Grant>, ApiTokenError>;
+
+    /// Forget anything remembered about `credentials`
+    ///
+    /// Called by [`ApiToken::revoke`](crate::ApiToken::revoke). Validators that cache
+    /// results, such as [`CachedValidator`](crate::CachedValidator), drop them here.
+    fn invalidate(&self, _credentials: &Credentials<'_>) {}
+
+    /// Forget anything remembered about any credentials
+    fn invalidate_all(&self) {}
+}
+
+/// Lets a validator be shared, e.g. to keep a handle for invalidating its cache
+#[rocket::async_trait]
+impl<V: TokenValidator + ?Sized> TokenValidator for Arc<V> {
+    async fn validate(&self, credentials: &Credentials<'_>) -> Result<Arc<Grant>, ApiTokenError> {
+        V::validate(self, credentials).await
+    }
+
+    fn invalidate(&self, credentials: &Credentials<'_>) {
+        V::invalidate(self, credentials)
+    }
+
+    fn invalidate_all(&self) {
+        V::invalidate_all(self)
+    }
+}
@@ -0,0 +1,71 @@
+mod common;
+
+use common::client;
+use rocket::http::Status;
+use rocket::local::blocking::Client;
+use rocket_apitoken::{ApiToken, Token, TokenSource};
+
+fn challenges(client: &Client, authorization: Option<&str>) -> Vec<String> {
+    let response = common::get(client, authorization).dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+    response
+        .headers()
+        .get("WWW-Authenticate")
+        .map(str::to_string)
+        .collect()
+}
+
+#[test]
+fn challenges_bearer_only_without_basic_users() {
+    let client = client(ApiToken::new(vec!["secret-token".to_string()], true));
+    assert_eq!(challenges(&client, None), ["Bearer"]);
+}
+
+#[test]
+fn challenges_basic_when_basic_users_exist() {
+    let api_token = ApiToken::from_tokens(
+        [
+            Token::bearer("secret-token"),
+            Token::basic("prometheus", "s3cret"),
+        ],
+        true,
+    )
+    .with_realm("metrics");
+    let client = client(api_token);
+    assert_eq!(
+        challenges(&client, None),
+        [
+            "Bearer realm=\"metrics\"",
+            "Basic realm=\"metrics\", charset=\"UTF-8\""
+        ]
+    );
+    let denied = challenges(&client, Some("Basic cHJvbWV0aGV1czp3cm9uZw=="));
+    assert_eq!(denied.len(), 2);
+    assert_eq!(denied[1], "Basic realm=\"metrics\", charset=\"UTF-8\"");
+}
+
+#[test]
+fn challenges_basic_with_default_realm() {
+    let api_token = ApiToken::from_tokens([Token::basic("prometheus", "s3cret")], true);
+    let client = client(api_token);
+    assert_eq!(
+        challenges(&client, None),
+        ["Bearer", "Basic realm=\"api\", charset=\"UTF-8\""]
+    );
+}
+
+#[test]
+fn no_basic_challenge_without_authorization_source() {
+    let api_token = ApiToken::from_tokens([Token::basic("prometheus", "s3cret")], true)
+        .with_sources(vec![TokenSource::Header("X-API-Key".into())]);
+    let client = client(api_token);
+    assert_eq!(challenges(&client, None), ["Bearer"]);
+}
+
+#[test]
+fn no_basic_challenge_once_basic_users_are_revoked() {
+    let api_token = ApiToken::from_tokens([Token::basic("prometheus", "s3cret")], true);
+    api_token.revoke_user("prometheus");
+    let client = client(api_token);
+    assert_eq!(challenges(&client, None), ["Bearer"]);
+}
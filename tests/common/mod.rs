@@ -0,0 +1,67 @@
+//! Fixture shared by the integration tests
+
+#![allow(dead_code)]
+
+use rocket::http::Header;
+use rocket::local::blocking::{Client, LocalRequest};
+use rocket::{Build, Rocket, Route};
+use rocket_apitoken::{ApiToken, Authorized};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// A route that only authorized requests reach
+#[rocket::get("/")]
+pub fn index(_authorized: Authorized) {}
+
+/// A Rocket managing `api_token`, serving `routes` with the crate's catchers
+pub fn rocket(api_token: ApiToken, routes: Vec<Route>) -> Rocket<Build> {
+    rocket::build()
+        .manage(api_token)
+        .mount("/", routes)
+        .register("/", rocket_apitoken::catchers())
+}
+
+/// A client of a Rocket managing `api_token` and serving [`index`]
+pub fn client(api_token: ApiToken) -> Client {
+    Client::untracked(rocket(api_token, rocket::routes![index])).unwrap()
+}
+
+/// A `GET /` request, with an `Authorization` header if one is given
+pub fn get<'c>(client: &'c Client, authorization: Option<&str>) -> LocalRequest<'c> {
+    let request = client.get("/");
+    match authorization {
+        Some(authorization) => {
+            request.header(Header::new("Authorization", authorization.to_string()))
+        }
+        None => request,
+    }
+}
+
+/// The time `secs` seconds after the Unix epoch
+pub fn at(secs: u64) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+/// A time that tests set by hand, read by the clocks it hands out
+#[derive(Clone)]
+pub struct Now(Arc<Mutex<SystemTime>>);
+
+impl Now {
+    pub fn new(at: SystemTime) -> Self {
+        Self(Arc::new(Mutex::new(at)))
+    }
+
+    /// A clock for [`ApiToken::with_clock`] reading this time
+    pub fn clock(&self) -> impl Fn() -> SystemTime + Send + Sync + 'static {
+        let now = self.0.clone();
+        move || *now.lock().unwrap()
+    }
+
+    pub fn set(&self, at: SystemTime) {
+        *self.0.lock().unwrap() = at;
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.0.lock().unwrap() += by;
+    }
+}
@@ -0,0 +1,52 @@
+use figment::providers::{Format, Toml};
+use rocket::figment::Figment;
+use rocket_apitoken::{ApiToken, ApiTokenConfig, Lockout, RateLimit, Token};
+
+const SHA256_OF_HASHED_TOKEN: &str =
+    "550643f45e135491c47bea94823b37278d5dd91375b285d44001d005d1603a33";
+
+const SECRETS: [&str; 4] = ["secret-token", "s3cret", "hashed-token", "config-token"];
+
+fn assert_redacted(debug: &str) {
+    for secret in SECRETS {
+        assert!(!debug.contains(secret), "{secret} in {debug}");
+    }
+    assert!(!debug.contains(&SHA256_OF_HASHED_TOKEN[..16]));
+}
+
+#[test]
+fn api_token_debug_hides_secrets() {
+    let api_token = ApiToken::from_tokens(
+        [
+            Token::bearer("secret-token"),
+            Token::basic("prometheus", "s3cret"),
+            Token::hashed(SHA256_OF_HASHED_TOKEN.parse().unwrap()),
+        ],
+        true,
+    )
+    .with_rate_limit(RateLimit::per_minute(10))
+    .with_lockout(Lockout::new(5));
+    let debug = format!("{:?}", api_token);
+    assert_redacted(&debug);
+    assert!(debug.contains("tokens: 3"), "{debug}");
+    assert_redacted(&format!("{:#?}", api_token));
+}
+
+#[test]
+fn config_debug_hides_secrets() {
+    let figment = Figment::from(Toml::string(
+        r#"
+        [apitoken]
+        tokens = [
+            "config-token",
+            { token = "secret-token", name = "dashboard" },
+            { username = "prometheus", token = "s3cret" },
+        ]
+        "#,
+    ));
+    let config: ApiTokenConfig = figment.focus("apitoken").extract().unwrap();
+    let debug = format!("{:?}", config);
+    assert_redacted(&debug);
+    assert!(debug.contains("dashboard"), "{debug}");
+    assert_redacted(&format!("{:?}", ApiToken::from_figment(&figment).unwrap()));
+}
@@ -0,0 +1,95 @@
+mod common;
+
+use figment::providers::{Format, Toml};
+use rocket::error::ErrorKind;
+use rocket::http::Status;
+use rocket::local::blocking::Client;
+use rocket::{Build, Rocket};
+use rocket_apitoken::{ApiToken, ApiTokenFairing};
+
+/// A Rocket serving [`common::index`] with `apitoken` configured by `toml`
+fn configured(toml: &str) -> Rocket<Build> {
+    let figment = rocket::Config::figment().merge(Toml::string(toml).nested());
+    rocket::custom(figment)
+        .attach(ApiTokenFairing)
+        .mount("/", rocket::routes![common::index])
+}
+
+fn managed(api_token: ApiToken) -> Rocket<Build> {
+    common::rocket(api_token, rocket::routes![common::index]).attach(ApiTokenFairing)
+}
+
+fn assert_aborts(rocket: Rocket<Build>) {
+    let error = Client::untracked(rocket).unwrap_err();
+    assert!(
+        matches!(error.kind(), ErrorKind::FailedFairings(_)),
+        "{:?}",
+        error.kind()
+    );
+}
+
+#[test]
+fn loads_the_configuration() {
+    let client = Client::untracked(configured(
+        r#"
+        [default.apitoken]
+        tokens = ["secret-token"]
+        "#,
+    ))
+    .unwrap();
+    let response = common::get(&client, Some("Bearer secret-token")).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+}
+
+#[test]
+fn aborts_on_invalid_configuration() {
+    assert_aborts(configured(
+        r#"
+        [default.apitoken]
+        tokens = [{ name = "no secret" }]
+        "#,
+    ));
+}
+
+#[test]
+fn aborts_on_empty_tokens() {
+    assert_aborts(configured(
+        r#"
+        [default.apitoken]
+        tokens = ["secret-token", ""]
+        "#,
+    ));
+}
+
+#[test]
+fn aborts_on_duplicate_tokens() {
+    assert_aborts(configured(
+        r#"
+        [default.apitoken]
+        tokens = ["secret-token", "secret-token"]
+        "#,
+    ));
+    let tokens = vec!["secret-token".to_string(), "secret-token".to_string()];
+    assert_aborts(managed(ApiToken::new(tokens, true)));
+}
+
+#[test]
+fn aborts_when_enabled_without_tokens() {
+    assert_aborts(configured("[default.apitoken]\nenabled = true"));
+    assert_aborts(managed(ApiToken::new(vec![], true)));
+}
+
+#[test]
+fn launches_when_disabled_without_tokens() {
+    let client = Client::untracked(configured("[default.apitoken]\nenabled = false")).unwrap();
+    assert_eq!(common::get(&client, None).dispatch().status(), Status::Ok);
+}
+
+#[test]
+fn can_be_attached_twice() {
+    let rocket =
+        configured("[default.apitoken]\ntokens = [\"secret-token\"]").attach(ApiTokenFairing);
+    let client = Client::untracked(rocket).unwrap();
+    let response = common::get(&client, Some("Bearer secret-token")).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+}
@@ -0,0 +1,43 @@
+mod common;
+
+use rocket::http::Status;
+use rocket::local::blocking::Client;
+use rocket_apitoken::ApiToken;
+
+fn client(enabled: bool) -> Client {
+    common::client(ApiToken::new(vec!["secret-token".to_string()], enabled))
+}
+
+fn get(client: &Client, authorization: Option<&str>) -> Status {
+    common::get(client, authorization).dispatch().status()
+}
+
+#[test]
+fn accepts_valid_token() {
+    assert_eq!(get(&client(true), Some("Bearer secret-token")), Status::Ok);
+}
+
+#[test]
+fn rejects_invalid_token() {
+    let client = client(true);
+    assert_eq!(
+        get(&client, Some("Bearer wrong-token")),
+        Status::Unauthorized
+    );
+    assert_eq!(
+        get(&client, Some("Bearer secret-token2")),
+        Status::Unauthorized
+    );
+}
+
+#[test]
+fn rejects_missing_token() {
+    assert_eq!(get(&client(true), None), Status::Unauthorized);
+}
+
+#[test]
+fn accepts_anything_when_disabled() {
+    let client = client(false);
+    assert_eq!(get(&client, None), Status::Ok);
+    assert_eq!(get(&client, Some("Bearer wrong-token")), Status::Ok);
+}
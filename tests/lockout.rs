@@ -0,0 +1,117 @@
+mod common;
+
+use common::Now;
+use rocket::http::{Header, Status};
+use rocket::local::blocking::Client;
+use rocket_apitoken::{ApiToken, Lockout};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+fn api_token(lockout: Lockout) -> ApiToken {
+    ApiToken::new(vec!["secret-token".to_string()], true).with_lockout(lockout)
+}
+
+fn client(lockout: Lockout) -> Client {
+    common::client(api_token(lockout))
+}
+
+fn get(client: &Client, remote: &str, token: &str, real_ip: Option<&str>) -> Status {
+    let remote: SocketAddr = remote.parse().unwrap();
+    let mut request = common::get(client, Some(&format!("Bearer {}", token))).remote(remote);
+    if let Some(real_ip) = real_ip {
+        request = request.header(Header::new("X-Real-IP", real_ip.to_string()));
+    }
+    request.dispatch().status()
+}
+
+#[test]
+fn spoofed_ip_header_does_not_dodge_lockout() {
+    let client = client(Lockout::new(3));
+    for guess in 0..3 {
+        let real_ip = format!("192.0.2.{}", guess);
+        let status = get(&client, "10.0.0.1:1000", "guess", Some(&real_ip));
+        assert_eq!(status, Status::Unauthorized);
+    }
+    let status = get(&client, "10.0.0.1:1000", "guess", Some("192.0.2.99"));
+    assert_eq!(status, Status::TooManyRequests);
+}
+
+#[test]
+fn spoofed_ip_header_does_not_lock_out_others() {
+    let client = client(Lockout::new(3));
+    for _ in 0..3 {
+        get(&client, "10.0.0.1:1000", "guess", Some("10.0.0.2"));
+    }
+    let status = get(&client, "10.0.0.2:1000", "secret-token", None);
+    assert_eq!(status, Status::Ok);
+}
+
+#[test]
+fn trusted_ip_header_identifies_clients() {
+    let client = client(Lockout::new(3).trust_ip_header());
+    for _ in 0..3 {
+        get(&client, "10.0.0.1:1000", "guess", Some("192.0.2.1"));
+    }
+    let status = get(&client, "10.0.0.1:1000", "secret-token", Some("192.0.2.1"));
+    assert_eq!(status, Status::TooManyRequests);
+    let status = get(&client, "10.0.0.1:1000", "secret-token", Some("192.0.2.2"));
+    assert_eq!(status, Status::Ok);
+}
+
+#[test]
+fn valid_requests_do_not_reset_failures() {
+    let client = client(Lockout::new(3));
+    for _ in 0..2 {
+        assert_eq!(
+            get(&client, "10.0.0.1:1000", "guess", None),
+            Status::Unauthorized
+        );
+        assert_eq!(
+            get(&client, "10.0.0.1:1000", "secret-token", None),
+            Status::Ok
+        );
+    }
+    assert_eq!(
+        get(&client, "10.0.0.1:1000", "guess", None),
+        Status::Unauthorized
+    );
+    let status = get(&client, "10.0.0.1:1000", "secret-token", None);
+    assert_eq!(status, Status::TooManyRequests);
+}
+
+#[test]
+fn backoff_survives_valid_requests() {
+    let now = Now::new(SystemTime::now());
+    let durations = Arc::new(Mutex::new(Vec::new()));
+    let lockout = {
+        let durations = durations.clone();
+        Lockout::new(1)
+            .with_duration(Duration::from_secs(10))
+            .on_lockout(move |event| durations.lock().unwrap().push(event.duration))
+    };
+    let client = common::client(api_token(lockout).with_clock(now.clock()));
+    let advance = |secs| now.advance(Duration::from_secs(secs));
+
+    assert_eq!(
+        get(&client, "10.0.0.1:1000", "guess", None),
+        Status::Unauthorized
+    );
+    advance(11);
+    assert_eq!(
+        get(&client, "10.0.0.1:1000", "secret-token", None),
+        Status::Ok
+    );
+    assert_eq!(
+        get(&client, "10.0.0.1:1000", "guess", None),
+        Status::Unauthorized
+    );
+    advance(21);
+    assert_eq!(
+        get(&client, "10.0.0.1:1000", "guess", None),
+        Status::Unauthorized
+    );
+    let durations = durations.lock().unwrap().clone();
+    let expected = [10, 20, 40].map(Duration::from_secs);
+    assert_eq!(durations, expected);
+}
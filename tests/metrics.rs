@@ -0,0 +1,28 @@
+#![cfg(feature = "metrics")]
+
+mod common;
+
+use rocket::http::{ContentType, Status};
+use rocket::local::blocking::Client;
+use rocket_apitoken::ApiToken;
+
+#[test]
+fn serves_metrics_of_the_managed_token() {
+    let api_token = ApiToken::new(vec!["secret-token".to_string()], true);
+    let rocket = common::rocket(api_token, rocket::routes![common::index])
+        .mount("/internal", rocket_apitoken::metrics_routes());
+    let client = Client::untracked(rocket).unwrap();
+    common::get(&client, Some("Bearer secret-token")).dispatch();
+    common::get(&client, Some("Bearer guess")).dispatch();
+
+    let response = client.get("/internal/metrics").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let content_type = response.content_type().unwrap();
+    assert!(content_type.media_type() == ContentType::Plain.media_type());
+    assert_eq!(content_type.param("version"), Some("0.0.4"));
+    let body = response.into_string().unwrap();
+    assert!(body.contains("apitoken_decisions_total{outcome=\"allowed\"} 1\n"));
+    assert!(body.contains("apitoken_decisions_total{outcome=\"denied\"} 1\n"));
+    assert!(body.contains("apitoken_failures_total{reason=\"unknown_token\"} 1\n"));
+    assert!(body.contains("apitoken_authorization_duration_seconds_count 2\n"));
+}
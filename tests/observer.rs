@@ -0,0 +1,134 @@
+mod common;
+
+use common::{at, Now};
+use rocket::http::{Header, Status};
+use rocket::local::blocking::Client;
+use rocket_apitoken::{
+    ApiToken, ApiTokenError, AuthEvent, AuthOutcome, AuthorizedAs, Identity, RateLimit,
+    RequireScope, Scope, Token,
+};
+use std::sync::{Arc, Mutex};
+
+struct Admin;
+
+impl Scope for Admin {
+    const NAME: &'static str = "admin";
+}
+
+#[rocket::get("/as")]
+fn authorized_as(_authorized: AuthorizedAs<'_>) {}
+
+#[rocket::get("/admin")]
+fn admin(_scope: RequireScope<Admin>) {}
+
+/// An outcome, the identity name and the failure reason, as observed
+type Decision = (AuthOutcome, Option<String>, Option<ApiTokenError>);
+
+fn client() -> (Client, Now, Arc<Mutex<Vec<Decision>>>) {
+    let now = Now::new(at(1_000));
+    let decisions = Arc::new(Mutex::new(Vec::new()));
+    let tokens = [
+        Token::bearer("dashboard-token")
+            .identity(Identity::new("dashboard"))
+            .expires_at(at(2_000))
+            .rate_limit(RateLimit::per_minute(2)),
+        Token::basic("prometheus", "s3cret"),
+    ];
+    let api_token = {
+        let decisions = decisions.clone();
+        ApiToken::from_tokens(tokens, true)
+            .with_clock(now.clock())
+            .with_observer(move |event: &AuthEvent<'_>| {
+                decisions.lock().unwrap().push((
+                    event.outcome,
+                    event.identity.map(|identity| identity.name().to_string()),
+                    event.reason.cloned(),
+                ))
+            })
+    };
+    let routes = rocket::routes![common::index, authorized_as, admin];
+    let client = Client::untracked(common::rocket(api_token, routes)).unwrap();
+    (client, now, decisions)
+}
+
+fn last(decisions: &Mutex<Vec<Decision>>) -> (AuthOutcome, Option<String>, String) {
+    let (outcome, identity, reason) = decisions.lock().unwrap().pop().unwrap();
+    let reason = reason.map_or_else(String::new, |reason| format!("{:?}", reason));
+    (outcome, identity, reason)
+}
+
+fn denied(identity: Option<&str>, reason: &str) -> (AuthOutcome, Option<String>, String) {
+    (
+        AuthOutcome::Denied,
+        identity.map(str::to_string),
+        reason.to_string(),
+    )
+}
+
+#[test]
+fn reports_the_identity_of_allowed_requests() {
+    let (client, _, decisions) = client();
+    for path in ["/", "/as"] {
+        let request = client.get(path).header(Header::new(
+            "Authorization",
+            "Basic cHJvbWV0aGV1czpzM2NyZXQ=",
+        ));
+        assert_eq!(request.dispatch().status(), Status::Ok);
+        assert_eq!(
+            last(&decisions),
+            (
+                AuthOutcome::Allowed,
+                Some("prometheus".into()),
+                String::new()
+            )
+        );
+    }
+}
+
+#[test]
+fn reports_unknown_tokens_without_identity() {
+    let (client, _, decisions) = client();
+    let response = common::get(&client, Some("Bearer guess")).dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+    assert_eq!(last(&decisions), denied(None, "UnknownToken"));
+}
+
+#[test]
+fn reports_the_identity_of_expired_tokens() {
+    let (client, now, decisions) = client();
+    now.set(at(2_000));
+    let response = common::get(&client, Some("Bearer dashboard-token")).dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+    assert_eq!(last(&decisions), denied(Some("dashboard"), "Expired"));
+}
+
+#[test]
+fn reports_the_identity_of_rate_limited_tokens() {
+    let (client, _, decisions) = client();
+    for _ in 0..2 {
+        let response = common::get(&client, Some("Bearer dashboard-token")).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+    }
+    let response = common::get(&client, Some("Bearer dashboard-token")).dispatch();
+    assert_eq!(response.status(), Status::TooManyRequests);
+    let (outcome, identity, reason) = last(&decisions);
+    assert_eq!(
+        (outcome, identity),
+        (AuthOutcome::Denied, Some("dashboard".into()))
+    );
+    assert!(reason.starts_with("RateLimited"), "{reason}");
+}
+
+#[test]
+fn reports_the_identity_of_tokens_without_the_scope() {
+    let (client, _, decisions) = client();
+    let response = client
+        .get("/admin")
+        .header(Header::new("Authorization", "Bearer dashboard-token"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Forbidden);
+    assert_eq!(
+        last(&decisions),
+        denied(Some("dashboard"), "InsufficientScope { scope: \"admin\" }")
+    );
+}
@@ -0,0 +1,48 @@
+mod common;
+
+use rocket::error::ErrorKind;
+use rocket::http::uri::Origin;
+use rocket::http::Status;
+use rocket::local::blocking::Client;
+use rocket_apitoken::{ApiToken, ApiTokenFairing, Authorized, TokenSource};
+
+#[rocket::get("/download?<name>")]
+fn download(_authorized: Authorized, name: &str, uri: &Origin<'_>) -> String {
+    format!("{name} {uri}")
+}
+
+fn rocket() -> rocket::Rocket<rocket::Build> {
+    let api_token = ApiToken::new(vec!["secret-token".to_string()], true)
+        .with_sources(vec![TokenSource::access_token()]);
+    common::rocket(api_token, rocket::routes![download])
+}
+
+#[test]
+fn removes_query_tokens_from_the_uri() {
+    let client = Client::untracked(rocket().attach(ApiTokenFairing)).unwrap();
+    let response = client
+        .get("/download?name=report&access_token=secret-token")
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(
+        response.into_string().unwrap(),
+        "report /download?name=report"
+    );
+    let response = client
+        .get("/download?name=report&access_token=wrong-token")
+        .dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+}
+
+#[test]
+fn aborts_launch_with_query_tokens_and_no_fairing() {
+    let error = Client::untracked(rocket()).unwrap_err();
+    assert!(matches!(error.kind(), ErrorKind::SentinelAborts(_)));
+}
+
+#[test]
+fn launches_without_fairing_when_query_tokens_are_not_used() {
+    let api_token = ApiToken::new(vec!["secret-token".to_string()], true);
+    let rocket = common::rocket(api_token, rocket::routes![download]);
+    assert!(Client::untracked(rocket).is_ok());
+}
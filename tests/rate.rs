@@ -0,0 +1,65 @@
+mod common;
+
+use common::{at, Now};
+use rocket::http::Status;
+use rocket::local::blocking::{Client, LocalResponse};
+use rocket_apitoken::{ApiToken, ApiTokenFairing, RateLimit};
+use std::time::Duration;
+
+fn client() -> (Client, Now) {
+    let now = Now::new(at(1_000));
+    let api_token = ApiToken::new(vec!["secret-token".to_string()], true)
+        .with_rate_limit(RateLimit::per_minute(2))
+        .with_clock(now.clock());
+    let rocket = common::rocket(api_token, rocket::routes![common::index]).attach(ApiTokenFairing);
+    (Client::untracked(rocket).unwrap(), now)
+}
+
+fn get(client: &Client) -> LocalResponse<'_> {
+    common::get(client, Some("Bearer secret-token")).dispatch()
+}
+
+/// The `RateLimit-Limit`, `RateLimit-Remaining` and `RateLimit-Reset` headers
+fn budget<'a>(response: &'a LocalResponse<'_>) -> [Option<&'a str>; 3] {
+    ["RateLimit-Limit", "RateLimit-Remaining", "RateLimit-Reset"]
+        .map(|name| response.headers().get_one(name))
+}
+
+#[test]
+fn reports_the_budget_on_allowed_requests() {
+    let (client, _) = client();
+    let response = get(&client);
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(budget(&response), [Some("2"), Some("1"), Some("30")]);
+    let response = get(&client);
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(budget(&response), [Some("2"), Some("0"), Some("60")]);
+}
+
+#[test]
+fn rejects_requests_over_the_budget() {
+    let (client, now) = client();
+    get(&client);
+    get(&client);
+    let response = get(&client);
+    assert_eq!(response.status(), Status::TooManyRequests);
+    assert_eq!(response.headers().get_one("Retry-After"), Some("30"));
+    assert_eq!(budget(&response), [Some("2"), Some("0"), Some("60")]);
+    assert!(response.headers().get_one("WWW-Authenticate").is_none());
+
+    now.advance(Duration::from_secs(29));
+    assert_eq!(get(&client).status(), Status::TooManyRequests);
+    now.advance(Duration::from_secs(1));
+    assert_eq!(get(&client).status(), Status::Ok);
+}
+
+#[test]
+fn does_not_charge_invalid_tokens() {
+    let (client, _) = client();
+    for _ in 0..3 {
+        let response = common::get(&client, Some("Bearer guess")).dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+        assert_eq!(budget(&response), [None; 3]);
+    }
+    assert_eq!(get(&client).status(), Status::Ok);
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use rocket::http::{Header, Status};
+use rocket::local::blocking::{Client, LocalResponse};
+use rocket_apitoken::{ApiToken, RequireScope, Scope, Token};
+
+struct Write;
+
+impl Scope for Write {
+    const NAME: &'static str = "write";
+}
+
+#[rocket::post("/")]
+fn write(_scope: RequireScope<Write>) {}
+
+fn client() -> Client {
+    let api_token = ApiToken::from_tokens(
+        [
+            Token::bearer("reader").scopes(["read"]),
+            Token::bearer("writer").scopes(["read", "write"]),
+        ],
+        true,
+    );
+    Client::untracked(common::rocket(api_token, rocket::routes![write])).unwrap()
+}
+
+fn post<'c>(client: &'c Client, token: &str) -> LocalResponse<'c> {
+    client
+        .post("/")
+        .header(Header::new("Authorization", format!("Bearer {}", token)))
+        .dispatch()
+}
+
+fn challenge(response: &LocalResponse<'_>) -> Option<String> {
+    response
+        .headers()
+        .get_one("WWW-Authenticate")
+        .map(str::to_string)
+}
+
+#[test]
+fn accepts_tokens_with_the_scope() {
+    assert_eq!(post(&client(), "writer").status(), Status::Ok);
+}
+
+#[test]
+fn forbids_valid_tokens_without_the_scope() {
+    let client = client();
+    let response = post(&client, "reader");
+    assert_eq!(response.status(), Status::Forbidden);
+    assert_eq!(
+        challenge(&response).as_deref(),
+        Some("Bearer error=\"insufficient_scope\", scope=\"write\"")
+    );
+}
+
+#[test]
+fn rejects_invalid_tokens_as_unauthorized() {
+    let client = client();
+    let response = post(&client, "guess");
+    assert_eq!(response.status(), Status::Unauthorized);
+    let challenge = challenge(&response).unwrap();
+    assert!(
+        challenge.starts_with("Bearer error=\"invalid_token\""),
+        "{challenge}"
+    );
+}
@@ -0,0 +1,74 @@
+#![cfg(feature = "cookies")]
+
+mod common;
+
+use rocket::error::ErrorKind;
+use rocket::form::Form;
+use rocket::http::{ContentType, Status};
+use rocket::local::blocking::Client;
+use rocket_apitoken::{ApiToken, AuthEvent, AuthOutcome, Lockout, Session, TokenSource};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+#[rocket::post("/login", data = "<token>")]
+async fn login(session: Session<'_>, token: Form<String>) -> Status {
+    match session.login(&token).await {
+        Ok(()) => Status::NoContent,
+        Err(error) => error.status(),
+    }
+}
+
+fn client(outcomes: Arc<Mutex<Vec<AuthOutcome>>>) -> Client {
+    let api_token = ApiToken::new(vec!["secret-token".to_string()], true)
+        .with_sources(vec![TokenSource::PrivateCookie("session".to_string())])
+        .with_lockout(Lockout::new(3))
+        .with_observer(move |event: &AuthEvent<'_>| outcomes.lock().unwrap().push(event.outcome));
+    Client::tracked(common::rocket(
+        api_token,
+        rocket::routes![login, common::index],
+    ))
+    .unwrap()
+}
+
+fn post_login(client: &Client, token: &str) -> Status {
+    let remote: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+    client
+        .post("/login")
+        .remote(remote)
+        .header(ContentType::Form)
+        .body(format!("token={}", token))
+        .dispatch()
+        .status()
+}
+
+#[test]
+fn login_sets_session_cookie() {
+    let client = client(Arc::default());
+    assert_eq!(client.get("/").dispatch().status(), Status::Unauthorized);
+    assert_eq!(post_login(&client, "secret-token"), Status::NoContent);
+    assert_eq!(client.get("/").dispatch().status(), Status::Ok);
+}
+
+#[test]
+fn failed_logins_lock_out_and_are_observed() {
+    let outcomes = Arc::new(Mutex::new(Vec::new()));
+    let client = client(outcomes.clone());
+    for _ in 0..3 {
+        assert_eq!(post_login(&client, "guess"), Status::Unauthorized);
+    }
+    assert_eq!(post_login(&client, "secret-token"), Status::TooManyRequests);
+    let outcomes = outcomes.lock().unwrap().clone();
+    assert_eq!(outcomes, [AuthOutcome::Denied; 4]);
+}
+
+#[test]
+fn aborts_launch_without_a_cookie_source() {
+    let api_token = ApiToken::new(vec!["secret-token".to_string()], true);
+    let rocket = common::rocket(api_token, rocket::routes![login]);
+    let error = Client::untracked(rocket).unwrap_err();
+    assert!(
+        matches!(error.kind(), ErrorKind::SentinelAborts(_)),
+        "{:?}",
+        error.kind()
+    );
+}
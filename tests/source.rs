@@ -0,0 +1,70 @@
+mod common;
+
+use rocket::http::{Header, Status};
+use rocket::local::blocking::Client;
+use rocket_apitoken::{ApiToken, Token, TokenSource};
+
+fn client(sources: Vec<TokenSource>) -> Client {
+    let api_token = ApiToken::from_tokens(
+        [Token::bearer("header-token"), Token::bearer("bearer-token")],
+        true,
+    )
+    .with_sources(sources);
+    common::client(api_token)
+}
+
+fn get(client: &Client, authorization: Option<&str>, api_key: Option<&str>) -> Status {
+    let mut request = common::get(client, authorization);
+    if let Some(api_key) = api_key {
+        request = request.header(Header::new("X-API-Key", api_key.to_string()));
+    }
+    request.dispatch().status()
+}
+
+fn authorization_then_api_key() -> Vec<TokenSource> {
+    vec![
+        TokenSource::Authorization,
+        TokenSource::Header("X-API-Key".to_string()),
+    ]
+}
+
+#[test]
+fn reads_the_whole_header_value() {
+    let client = client(vec![TokenSource::Header("X-API-Key".to_string())]);
+    assert_eq!(get(&client, None, Some("header-token")), Status::Ok);
+    assert_eq!(get(&client, None, Some(" header-token ")), Status::Ok);
+    assert_eq!(
+        get(&client, None, Some("Bearer header-token")),
+        Status::Unauthorized
+    );
+    assert_eq!(
+        get(&client, Some("Bearer bearer-token"), None),
+        Status::Unauthorized
+    );
+}
+
+#[test]
+fn falls_back_to_later_sources() {
+    let client = client(authorization_then_api_key());
+    assert_eq!(get(&client, None, Some("header-token")), Status::Ok);
+    assert_eq!(get(&client, Some("Bearer bearer-token"), None), Status::Ok);
+    assert_eq!(get(&client, None, None), Status::Unauthorized);
+}
+
+#[test]
+fn uses_the_first_source_present() {
+    let authorization_first = client(authorization_then_api_key());
+    assert_eq!(
+        get(
+            &authorization_first,
+            Some("Bearer guess"),
+            Some("header-token")
+        ),
+        Status::Unauthorized
+    );
+    let api_key_first = client(authorization_then_api_key().into_iter().rev().collect());
+    assert_eq!(
+        get(&api_key_first, Some("Bearer guess"), Some("header-token")),
+        Status::Ok
+    );
+}
@@ -0,0 +1,66 @@
+mod common;
+
+use common::{at, Now};
+use rocket::http::Status;
+use rocket::local::blocking::Client;
+use rocket_apitoken::{ApiToken, ApiTokenError, Authorized, Token};
+use std::time::Duration;
+
+#[rocket::get("/")]
+fn index(authorized: Result<Authorized, ApiTokenError>) -> String {
+    match authorized {
+        Ok(_) => "ok".to_string(),
+        Err(error) => format!("{:?}", error),
+    }
+}
+
+/// A client whose `ApiToken` clock reads the returned time
+fn client() -> (Client, Now) {
+    let now = Now::new(at(0));
+    let token = Token::bearer("secret-token")
+        .not_before(at(1_000))
+        .expires_at(at(2_000));
+    let api_token = ApiToken::from_tokens([token], true).with_clock(now.clock());
+    let rocket = common::rocket(api_token, rocket::routes![index]);
+    (Client::untracked(rocket).unwrap(), now)
+}
+
+fn get(client: &Client) -> String {
+    let response = common::get(client, Some("Bearer secret-token")).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    response.into_string().unwrap()
+}
+
+#[test]
+fn rejects_token_before_not_before() {
+    let (client, now) = client();
+    now.set(at(999));
+    assert_eq!(get(&client), "NotYetValid");
+}
+
+#[test]
+fn accepts_token_from_not_before() {
+    let (client, now) = client();
+    now.set(at(1_000));
+    assert_eq!(get(&client), "ok");
+    now.set(at(1_999));
+    assert_eq!(get(&client), "ok");
+}
+
+#[test]
+fn rejects_token_at_expires_at() {
+    let (client, now) = client();
+    now.set(at(2_000));
+    assert_eq!(get(&client), "Expired");
+    now.set(at(3_000));
+    assert_eq!(get(&client), "Expired");
+}
+
+#[test]
+fn accepts_token_until_expires_at() {
+    let (client, now) = client();
+    now.set(at(2_000) - Duration::from_nanos(1));
+    assert_eq!(get(&client), "ok");
+    now.set(at(2_000));
+    assert_eq!(get(&client), "Expired");
+}